@@ -1,14 +1,16 @@
 use std::collections::HashMap;
 use crate::error::LispErr;
 use crate::error::LispErr::Runtime;
-use crate::lispval::LispVal;
+use crate::lispval::{LispVal, PrimitiveFn};
+use crate::symbol::Symbol;
 use std::rc::Rc;
 use std::cell::RefCell;
 
 #[derive(Clone, Debug, PartialEq, Default)]
 pub struct Env {
     parent: Option<Rc<RefCell<Env>>>,
-    vars: HashMap<String, LispVal>,
+    /// `None` for a variable whose internal definition has not been evaluated yet.
+    vars: HashMap<Symbol, Option<LispVal>>,
 }
 
 impl Env {
@@ -19,31 +21,53 @@ impl Env {
         Env { parent: Some(parent), vars: HashMap::new() }
     }
 
-    pub fn set(&mut self, name: &str, val: LispVal) -> Result<LispVal, LispErr>{
-        if self.vars.contains_key(name) {
-            self.vars.insert(name.to_string(), val.clone());
+    /// The outermost environment `env` is nested in.
+    pub fn root(env: &Rc<RefCell<Env>>) -> Rc<RefCell<Env>> {
+        match &env.borrow().parent {
+            Some(p) => Env::root(p),
+            None => env.clone(),
+        }
+    }
+
+    pub fn set(&mut self, name: impl Into<Symbol>, val: LispVal) -> Result<LispVal, LispErr>{
+        let name = name.into();
+        if let Some(v) = self.vars.get_mut(&name) {
+            *v = Some(val.clone());
             Ok(val)
         } else {
             match &self.parent {
-                None => Err(Runtime("Variable is not defined".to_string())),
+                None => Err(Runtime(format!("Variable {} is not defined", name))),
                 Some(c) => c.borrow_mut().set(name, val)
             }
         }
     }
 
-    pub fn define(&mut self, name: &str, val: LispVal) -> Result<LispVal, LispErr> {
-        self.vars.insert(name.to_string(), val.clone());
+    pub fn define(&mut self, name: impl Into<Symbol>, val: LispVal) -> Result<LispVal, LispErr> {
+        self.vars.insert(name.into(), Some(val.clone()));
         Ok(val)
     }
 
-    pub fn get(&self, name: &str) -> Option<LispVal>{
-        let res = &self.vars.get(name);
-        if res.is_some() {
-            return res.cloned();
-        }
-        match &self.parent {
-            Some(p) => p.borrow().get(name).clone(),
-            None => None
+    /// Binds `name` to the built-in procedure `func`, which prints under that name.
+    pub fn define_primitive(&mut self, name: &str, func: PrimitiveFn) -> Result<LispVal, LispErr> {
+        self.define(name, LispVal::PrimitiveFunc { name: Some(Symbol::intern(name)), func })
+    }
+
+    /// Binds `name` without giving it a value yet, shadowing any outer binding. Reading it
+    /// before it is defined is an error.
+    pub fn declare(&mut self, name: Symbol) {
+        self.vars.insert(name, None);
+    }
+
+    /// The value of `name`, or `None` if it is not bound.
+    pub fn get(&self, name: impl Into<Symbol>) -> Result<Option<LispVal>, LispErr> {
+        let name = name.into();
+        match self.vars.get(&name) {
+            Some(Some(v)) => Ok(Some(v.clone())),
+            Some(None) => Err(Runtime(format!("Variable {} is used before its definition", name))),
+            None => match &self.parent {
+                Some(p) => p.borrow().get(name),
+                None => Ok(None)
+            }
         }
     }
 }
@@ -6,6 +6,10 @@ pub enum LispErr {
     Runtime(String),
     WrongExpression(String),
     Expected(LispVal),
+    /// An operating system error, with the file or port it happened on.
+    Io(String, String),
+    /// A value of the wrong type, with a description of what was expected.
+    WrongType(String, LispVal),
 }
 
 impl Display for LispErr {
@@ -13,7 +17,13 @@ impl Display for LispErr {
         match &self {
             LispErr::Runtime(s) => write!(f, "{}", s),
             LispErr::WrongExpression(n) => write!(f, "{}", n),
-            LispErr::Expected(v) => write!(f, "Expected {}", v)
+            LispErr::Expected(v) => write!(f, "Expected {}", v),
+            LispErr::Io(path, e) => write!(f, "{}: {}", path, e),
+            LispErr::WrongType(expected, v) => write!(f, "Expected {} but got {}", expected, v),
         }
     }
-}
\ No newline at end of file
+}
+
+pub fn io_error(path: &str, e: std::io::Error) -> LispErr {
+    LispErr::Io(path.to_string(), e.to_string())
+}
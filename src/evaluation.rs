@@ -5,39 +5,56 @@ use crate::env::Env;
 use crate::error::LispErr;
 use crate::error::LispErr::{Expected, Runtime, WrongExpression};
 use crate::lispval::LispVal;
-use crate::lispval::LispVal::{Atom, Boolean, Func, PrimitiveFunc};
+use crate::lispval::LispVal::{Boolean, Func, PrimitiveFunc};
+use crate::parameter::{parameter, with_parameters};
+use crate::promise::{Promise, PromiseState};
+use crate::library::{define_library, import};
+use crate::record::define_record;
+use crate::symbol::Symbol;
 
 pub fn eval(v: &LispVal, env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
     match v {
-        LispVal::Atom(var) => get_var(&var, env),
+        LispVal::Symbol(var) => get_var(*var, env),
         LispVal::LispString(_) => Ok(v.clone()),
         LispVal::Number(_) => Ok(v.clone()),
         LispVal::Boolean(_) => Ok(v.clone()),
         LispVal::Quote(q) => Ok(*q.clone()),
-        LispVal::List(v) => eval_list(&v, env),
-        LispVal::DottedList(_, _) => Ok(v.clone()),
-        LispVal::Func { .. } => Ok(v.clone()),
-        LispVal::PrimitiveFunc(_) => Ok(v.clone()),
-        //LispVal::File(_) => Ok(v.clone()),
+        LispVal::Nil => Ok(v.clone()),
+        LispVal::Pair(_) => eval_list(&v.to_vec()?, env),
+        LispVal::Func { .. } | LispVal::CaseLambda { .. } => Ok(v.clone()),
+        LispVal::PrimitiveFunc { .. } | LispVal::NativeFunc(_) => Ok(v.clone()),
+        LispVal::HashTable(_) => Ok(v.clone()),
+        LispVal::Char(_) | LispVal::Port(_) | LispVal::Environment(_) | LispVal::RecordType(_) | LispVal::Record(_) | LispVal::Values(_) | LispVal::Promise(_) | LispVal::Parameter(_) | LispVal::Eof | LispVal::Unspecified => Ok(v.clone()),
     }
 }
 
-fn eval_list(list: &Vec<LispVal>, env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+fn eval_list(list: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
     let a = [evaluate_if,
+        begin,
         define_var,
-        define_vararg_func,
         define_func,
         set_var,
+        define_record_type,
+        eval_define_library,
+        eval_import,
+        define_values,
+        receive,
+        let_values,
+        delay,
+        stream_cons,
+        parameterize,
+        eval_do,
+        eval_while,
+        dotimes,
+        dolist,
+        case_lambda,
         eval_lambda,
         eval_function_call];
-    match eval_any_of(list, env, &a) {
-        Ok(r) => Ok(r),
-        Err(e) => Err(e),
-    }
+    eval_any_of(list, env, &a)
 }
 
-fn eval_any_of<T>(list: &Vec<LispVal>, env: &Rc<RefCell<Env>>, f: &[T]) -> Result<LispVal, LispErr>
-    where T: Fn(&Vec<LispVal>, &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+fn eval_any_of<T>(list: &[LispVal], env: &Rc<RefCell<Env>>, f: &[T]) -> Result<LispVal, LispErr>
+    where T: Fn(&[LispVal], &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
     for e in f {
         match e(list, env) {
             Ok(val) => return Ok(val),
@@ -57,29 +74,21 @@ fn consume(opt: Option<&LispVal>, e: &str) -> Result<LispVal, LispErr> {
 }
 
 fn consume_exact(opt: Option<&LispVal>, expected: LispVal) -> Result<LispVal, LispErr> {
-    let val = consume(opt, format!("Expected {}", expected).as_str())?;
-    if val.eq(&expected) {
-        Ok(val)
-    } else {
-        Err(Expected(expected))
+    match opt {
+        Some(val) if *val == expected => Ok(val.clone()),
+        Some(_) => Err(Expected(expected)),
+        None => Err(Runtime(format!("Expected {}", expected))),
     }
 }
 
 
 fn consume_list(opt: Option<&LispVal>) -> Result<Vec<LispVal>, LispErr> {
-    match consume(opt, format!("Expected list").as_str())? {
-        LispVal::List(r) => Ok(r),
+    match consume(opt, "Expected list")? {
+        r @ (LispVal::Nil | LispVal::Pair(_)) => r.to_vec(),
         _ => Err(Runtime("Expected list".to_string())),
     }
 }
 
-fn consume_dotted_list(opt: Option<&LispVal>) -> Result<(Vec<LispVal>, Box<LispVal>), LispErr> {
-    match consume(opt, format!("Expected dotted list").as_str())? {
-        LispVal::DottedList(v, r) => Ok((v, r)),
-        _ => Err(WrongExpression("Expected list".to_string())),
-    }
-}
-
 fn nothing_to_consume(opt: Option<&LispVal>) -> Result<(), LispErr> {
     match opt {
         Some(v) => Err(Runtime(format!("Error unexpected value {}", v))),
@@ -87,138 +96,604 @@ fn nothing_to_consume(opt: Option<&LispVal>) -> Result<(), LispErr> {
     }
 }
 
-fn extract_str_from_atom(r: Result<LispVal, LispErr>) -> Result<String, LispErr> {
+fn extract_symbol(r: Result<LispVal, LispErr>) -> Result<Symbol, LispErr> {
     match r {
-        Ok(Atom(s)) => Ok(s),
+        Ok(LispVal::Symbol(s)) => Ok(s),
         Err(e) => Err(e),
-        Ok(other) => Err(Runtime(format!("Expected atom but got {}", other))),
+        Ok(other) => Err(Runtime(format!("Expected symbol but got {}", other))),
     }
 }
 
-fn define_var(list: &Vec<LispVal>, env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+/// `v` as the value of a variable, which cannot hold multiple values.
+fn single_value(v: LispVal) -> Result<LispVal, LispErr> {
+    match v {
+        LispVal::Values(values) => Err(Runtime(format!("Expected a single value but got {} values", values.len()))),
+        v => Ok(v),
+    }
+}
+
+fn define_var(list: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
     let mut iter = list.iter();
-    to_wrong_expr(consume_exact(iter.next(), Atom("define".to_string())))?;
+    to_wrong_expr(consume_exact(iter.next(), LispVal::symbol("define")))?;
     let name = match to_wrong_expr(consume(iter.next(), "Expect variable name"))? {
-        Atom(s) => s,
+        LispVal::Symbol(s) => s,
         _ => return Err(WrongExpression("Expect variable name".to_string())),
     };
     let val = consume(iter.next(), "Expect variable value").map(|a| eval(&a, env))??;
+    let val = single_value(val)?.named(name);
     nothing_to_consume(iter.next())?;
-    env.borrow_mut().define(&name, val.clone())
+    env.borrow_mut().define(name, val.clone())
 }
 
-fn set_var(list: &Vec<LispVal>, env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+fn set_var(list: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
     let mut iter = list.iter();
-    to_wrong_expr(consume_exact(iter.next(), Atom("set!".to_string())))?;
-    let name = extract_str_from_atom(consume(iter.next(), "Expect variable name"))?;
-    let val = consume(iter.next(), "Expect variable value").map(|a| eval(&a, env))??;
+    to_wrong_expr(consume_exact(iter.next(), LispVal::symbol("set!")))?;
+    let name = extract_symbol(consume(iter.next(), "Expect variable name"))?;
+    let val = single_value(consume(iter.next(), "Expect variable value").map(|a| eval(&a, env))??)?;
     nothing_to_consume(iter.next())?;
-    env.borrow_mut().set(&name, val.clone())
+    env.borrow_mut().set(name, val.clone())
 }
 
-fn get_var(name: &str, env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
-    match env.borrow_mut().get(name) {
-        Some(v) => Ok(v.clone()),
+fn get_var(name: Symbol, env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    match env.borrow().get(name)? {
+        Some(v) => Ok(v),
         None => Err(Runtime(format!("Variable {} is not defined", name))),
     }
 }
 
-fn define_vararg_func(list: &Vec<LispVal>, env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+/// `(define (name . formals) body ...)`
+fn define_func(list: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
     let mut iter = list.iter();
-    to_wrong_expr(consume_exact(iter.next(), Atom("define".to_string())))?;
-    let (definition, vararg) =  consume_dotted_list(iter.next())?;
-    let name = extract_str_from_atom(consume(definition.first(), "Expect function name"))?;
-    let params: &Vec<String> = &definition[1..].iter().map(|a| format!("{}", a)).collect();
+    to_wrong_expr(consume_exact(iter.next(), LispVal::symbol("define")))?;
+    let LispVal::Pair(definition) = consume(iter.next(), "Expect function definition")? else {
+        return Err(WrongExpression("Expect function definition".to_string()));
+    };
+    let name = extract_symbol(Ok(definition.car()))?;
+    let func = lambda(Some(name), &definition.cdr(), iter.as_slice(), env)?;
+    env.borrow_mut().define(name, func)
+}
+
+fn define_record_type(list: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    to_wrong_expr(consume_exact(list.first(), LispVal::symbol("define-record-type")))?;
+    define_record(&list[1..], env)
+}
+
+fn eval_define_library(list: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    to_wrong_expr(consume_exact(list.first(), LispVal::symbol("define-library")))?;
+    define_library(&list[1..])
+}
+
+fn eval_import(list: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    to_wrong_expr(consume_exact(list.first(), LispVal::symbol("import")))?;
+    import(&list[1..], env)
+}
+
+/// The parameters of a lambda list such as `(a b #!optional (c 1) d #!rest r #!key (e 2))`.
+/// `(a b . r)` and a bare `r` are shorthand for a `#!rest` parameter.
+#[derive(Default)]
+struct Formals {
+    args: Vec<Symbol>,
+    optional: Vec<(Symbol, LispVal)>,
+    vararg: Option<Symbol>,
+    keys: Vec<(Symbol, LispVal)>,
+}
+
+/// `name` or `(name default)`, where a missing default is `#f`.
+fn parameter_with_default(v: &LispVal) -> Result<(Symbol, LispVal), LispErr> {
+    if let LispVal::Symbol(s) = v {
+        return Ok((*s, Boolean(false)));
+    }
+    match v.to_vec()?.as_slice() {
+        [LispVal::Symbol(s), default] => Ok((*s, default.clone())),
+        _ => Err(Runtime(format!("Expected parameter or (parameter default) but got {}", v))),
+    }
+}
 
-    let mut body: Vec<LispVal> = Vec::new();
-    for v in iter {
-        body.push(v.clone());
+fn parse_formals(formals: &LispVal) -> Result<Formals, LispErr> {
+    let (params, tail) = match formals {
+        LispVal::Symbol(_) => (vec![], formals.clone()),
+        _ => formals.split_improper()?,
+    };
+    let mut result = Formals::default();
+    if tail != LispVal::Nil {
+        result.vararg = Some(extract_symbol(Ok(tail))?);
     }
-    let vararg = vararg.to_string();
-    let func = Func { args: params.clone(), body: body, vararg: Some(vararg), closure: env.clone() };
-    env.borrow_mut().define(&name, func.clone())
+    let mut marker = None;
+    for p in params {
+        if let LispVal::Symbol(s) = &p {
+            let name = s.name();
+            if matches!(&*name, "#!optional" | "#!rest" | "#!key") {
+                marker = Some(name);
+                continue;
+            }
+        }
+        match marker.as_deref() {
+            None => result.args.push(extract_symbol(Ok(p))?),
+            Some("#!optional") => result.optional.push(parameter_with_default(&p)?),
+            Some("#!rest") if result.vararg.is_none() => result.vararg = Some(extract_symbol(Ok(p))?),
+            Some("#!key") => result.keys.push(parameter_with_default(&p)?),
+            _ => return Err(Runtime(format!("Unexpected parameter {} in {}", p, formals))),
+        }
+    }
+    Ok(result)
+}
+
+/// A procedure taking `formals` whose body runs in a child of `env`.
+fn lambda(name: Option<Symbol>, formals: &LispVal, body: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    let Formals { args, optional, vararg, keys } = parse_formals(formals)?;
+    Ok(Func { name, args, optional: optional.into(), vararg, keys: keys.into(), body: body.into(), closure: env.clone() })
 }
 
-fn define_func(list: &Vec<LispVal>, env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+fn eval_lambda(list: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
     let mut iter = list.iter();
-    to_wrong_expr(consume_exact(iter.next(), Atom("define".to_string())))?;
-    let definition = consume_list(iter.next())?;
-    let name = extract_str_from_atom(consume(definition.first(), "Expect function name"))?;
-    let params: &Vec<String> = &definition[1..].iter().map(|a| format!("{}", a)).collect();
+    to_wrong_expr(consume_exact(iter.next(), LispVal::symbol("lambda")))?;
+    let formals = consume(iter.next(), "Expect formals")?;
+    lambda(None, &formals, iter.as_slice(), env)
+}
 
-    let mut body: Vec<LispVal> = Vec::new();
-    for v in iter {
-        body.push(v.clone());
+/// `(case-lambda (formals body ...) ...)`
+fn case_lambda(list: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    let mut iter = list.iter();
+    to_wrong_expr(consume_exact(iter.next(), LispVal::symbol("case-lambda")))?;
+    let clauses = iter.map(|clause| match clause {
+        LispVal::Pair(p) => lambda(None, &p.car(), &p.cdr().to_vec()?, env),
+        _ => Err(Runtime(format!("Expected (formals body ...) but got {}", clause))),
+    }).collect::<Result<Vec<_>, _>>()?;
+    Ok(LispVal::CaseLambda { name: None, clauses })
+}
+
+fn eval_function_call(list: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    if list.is_empty() {
+        return Err(Runtime("Expected function".to_string()));
     }
-    let func = Func { args: params.clone(), body: body, vararg: None, closure: env.clone() };
-    env.borrow_mut().define(&name, func.clone())
+    let f = eval(&list[0], env)?;
+    let keys_from = keyword_position(&f);
+    let args = list[1..].iter().enumerate()
+        .map(|(i, v)| match keyword(v) {
+            Some(_) if keys_from.is_some_and(|k| i >= k) => Ok(v.clone()),
+            _ => eval(v, env),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    call_function(&f, &args, env)
 }
 
-fn eval_lambda(list: &Vec<LispVal>, env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
-    let mut iter = list.iter();
-    to_wrong_expr(consume_exact(iter.next(), Atom("lambda".to_string())))?;
-    let definition = consume_list(iter.next())?;
-    let params: &Vec<String> = &definition[0..].iter().map(|a| format!("{}", a)).collect();
-    let mut body: Vec<LispVal> = Vec::new();
-    for v in iter {
-        body.push(v.clone());
+/// Index of the first argument that may be a keyword, if `f` takes `#!key` parameters.
+/// Keywords such as `size:` are only passed as themselves from there on; anywhere else
+/// they are ordinary variable references.
+fn keyword_position(f: &LispVal) -> Option<usize> {
+    match f {
+        Func { args, keys, .. } if !keys.is_empty() => Some(args.len()),
+        _ => None,
     }
-    Ok(Func { args: params.clone(), body: body, vararg: None, closure: env.clone()})
 }
-fn eval_function_call(list: &Vec<LispVal>, env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
-    if list.len() < 1 {
-        return Err(Runtime("Expected function".to_string()));
+
+/// Whether the procedure `f` can be called with `n` arguments.
+fn accepts(f: &LispVal, n: usize) -> bool {
+    match f {
+        Func { args, optional, vararg, keys, .. } =>
+            n >= args.len() && (vararg.is_some() || !keys.is_empty() || n <= args.len() + optional.len()),
+        _ => false,
     }
-    let list:Result<Vec<LispVal>, LispErr> = list.iter().map(|v|eval(v, &env)).collect();
-    let list = list?;
-    call_function(&list[0], &list[1..], env)
 }
 
-pub fn call_function(f: &LispVal, list: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
-    if list.len() < 1 {
-        return Err(Runtime("Expected function".to_string()));
+/// The parameter a keyword argument such as `size:` is passed to.
+fn keyword(v: &LispVal) -> Option<Symbol> {
+    match v {
+        LispVal::Symbol(s) => {
+            let name = s.name();
+            name.strip_suffix(':').filter(|n| !n.is_empty()).map(Symbol::intern)
+        }
+        _ => None,
     }
+}
 
-    if let PrimitiveFunc(func) = f {
-        return func(&list, env)
+/// Binds `keys` to the values following their keywords in `list`, or to their defaults.
+/// Keywords that no parameter takes are left for the rest parameter, if there is one.
+fn bind_keys(keys: &[(Symbol, LispVal)], list: &[LispVal], rest: bool, env: &Rc<RefCell<Env>>) -> Result<(), LispErr> {
+    let mut given = vec![];
+    for pair in list.chunks(2) {
+        let (Some(key), [_, v]) = (keyword(&pair[0]), pair) else {
+            return Err(Runtime(format!("Expected keyword and value but got {}", LispVal::list(pair.to_vec()))));
+        };
+        if keys.iter().any(|(k, _)| *k == key) {
+            given.push((key, v));
+        } else if !rest {
+            return Err(Runtime(format!("Unexpected keyword {}", pair[0])));
+        }
     }
+    for (key, default) in keys {
+        let v = match given.iter().find(|(k, _)| k == key) {
+            Some((_, v)) => (*v).clone(),
+            None => eval(default, env)?,
+        };
+        env.borrow_mut().define(*key, v)?;
+    }
+    Ok(())
+}
 
-    let Func { args, body, vararg, closure } = f
+pub fn call_function(f: &LispVal, list: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    match f {
+        PrimitiveFunc { func, .. } => return func(list, env),
+        LispVal::NativeFunc(func) => return func.call(list, env),
+        LispVal::Parameter(p) => {
+            if !list.is_empty() {
+                return Err(Runtime("Incorrect argument count".to_string()));
+            }
+            return Ok(p.get());
+        }
+        LispVal::CaseLambda { clauses, .. } => {
+            let clause = clauses.iter().find(|c| accepts(c, list.len()))
+                .ok_or_else(|| Runtime(format!("No clause of {} takes {} arguments", f, list.len())))?;
+            return call_function(clause, list, env);
+        }
+        _ => (),
+    }
+
+    let Func { args, optional, vararg, keys, body, closure, .. } = f
         else {
             return Err(Runtime(format!("Incorrect function call {}", f)));
         };
 
-    if (vararg.is_none() && list.len() != args.len()) || list.len() < args.len() {
+    if !accepts(f, list.len()) {
         return Err(Runtime("Incorrect argument count".to_string()));
     }
 
     let closure = Rc::new(RefCell::new(Env::child(closure.clone())));
 
     for (i, arg) in args.iter().enumerate() {
-        _ = closure.borrow_mut().define(arg, list[i].clone());
+        _ = closure.borrow_mut().define(*arg, list[i].clone());
+    }
+
+    // Optional parameters take the positional arguments up to the first keyword.
+    let mut rest = &list[args.len()..];
+    for (arg, default) in optional.iter() {
+        let v = match rest.split_first() {
+            Some((v, tail)) if keys.is_empty() || keyword(v).is_none() => {
+                rest = tail;
+                v.clone()
+            }
+            _ => eval(default, &closure)?,
+        };
+        closure.borrow_mut().define(*arg, v)?;
     }
 
     if  let Some(vararg_name) = vararg {
-        let mut var_arg_value = vec![];
-        for i in args.len()..list.len() {
-            var_arg_value.push(list[i].clone());
+        closure.borrow_mut().define(*vararg_name, LispVal::list(rest.to_vec()))?;
+    }
+
+    if !keys.is_empty() {
+        bind_keys(keys, rest, vararg.is_some(), &closure)?;
+    }
+
+    eval_body(body, &closure)
+}
+
+/// Calls `f` with the values `v` stands for as its arguments. A single value is passed
+/// through without allocating.
+pub fn call_with_values(f: &LispVal, v: &LispVal, env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    match v {
+        LispVal::Values(values) => call_function(f, values, env),
+        v => call_function(f, std::slice::from_ref(v), env),
+    }
+}
+
+/// Binds `formals`, a list of symbols that may end in a rest symbol or a single rest
+/// symbol, to the values `v` stands for.
+fn bind_values(formals: &LispVal, v: LispVal, env: &Rc<RefCell<Env>>) -> Result<(), LispErr> {
+    let (params, rest) = match formals {
+        LispVal::Symbol(_) => (vec![], formals.clone()),
+        _ => formals.split_improper()?,
+    };
+    let values = v.into_values();
+    if values.len() < params.len() || (rest == LispVal::Nil && values.len() > params.len()) {
+        return Err(Runtime(format!("Expected {} values for {} but got {}", params.len(), formals, values.len())));
+    }
+    let mut e = env.borrow_mut();
+    for (p, v) in params.iter().zip(&values) {
+        e.define(extract_symbol(Ok(p.clone()))?, v.clone())?;
+    }
+    if rest != LispVal::Nil {
+        e.define(extract_symbol(Ok(rest))?, LispVal::list(values[params.len()..].to_vec()))?;
+    }
+    Ok(())
+}
+
+/// The names bound by `v` if it is a definition: a `define`, `define-values`,
+/// `define-record-type` or a non-empty `begin` of definitions. Record types bind their
+/// names when evaluated, so none are returned for them.
+fn defined_names(v: &LispVal) -> Result<Option<Vec<Symbol>>, LispErr> {
+    let LispVal::Pair(p) = v else {
+        return Ok(None);
+    };
+    let LispVal::Symbol(s) = p.car() else {
+        return Ok(None);
+    };
+    let args = p.cdr();
+    let first = match &args {
+        LispVal::Pair(a) => a.car(),
+        _ => LispVal::Nil,
+    };
+    match &*s.name() {
+        "define" => match first {
+            LispVal::Symbol(name) => Ok(Some(vec![name])),
+            LispVal::Pair(definition) => Ok(Some(vec![extract_symbol(Ok(definition.car()))?])),
+            _ => Ok(None),
+        },
+        "define-values" => {
+            let (params, rest) = match first {
+                LispVal::Symbol(_) => (vec![], first),
+                _ => first.split_improper()?,
+            };
+            let mut names = params.into_iter().map(|p| extract_symbol(Ok(p))).collect::<Result<Vec<_>, _>>()?;
+            if rest != LispVal::Nil {
+                names.push(extract_symbol(Ok(rest))?);
+            }
+            Ok(Some(names))
+        }
+        "define-record-type" => Ok(Some(vec![])),
+        "begin" if args != LispVal::Nil => {
+            let mut names = vec![];
+            for form in args.to_vec()? {
+                match defined_names(&form)? {
+                    Some(defined) => names.extend(defined),
+                    None => return Ok(None),
+                }
+            }
+            Ok(Some(names))
         }
-        closure.borrow_mut().define(&vararg_name, LispVal::List(var_arg_value))?;
+        _ => Ok(None),
     }
+}
 
-    let mut result = Err(Runtime("not executed".to_string()));
+/// Evaluates a body in its own scope `env`. The definitions at its head are all bound
+/// before the first is evaluated, as with `letrec*`, so they can refer to each other and
+/// shadow outer bindings throughout the body.
+fn eval_body(body: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    let mut names = vec![];
+    let mut expression = false;
+    for form in body {
+        match defined_names(form)? {
+            Some(_) if expression => return Err(Runtime(format!("Definition after an expression in body: {}", form))),
+            Some(defined) => names.extend(defined),
+            None => expression = true,
+        }
+    }
+    if !names.is_empty() {
+        let mut e = env.borrow_mut();
+        for name in names {
+            e.declare(name);
+        }
+    }
+    let mut result = LispVal::Unspecified;
     for b in body {
-        result = eval(&b, &closure);
-        if result.is_err() {
-            return result;
+        result = eval(b, env)?;
+    }
+    Ok(result)
+}
+
+/// `(define-values formals expr)`
+fn define_values(list: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    let mut iter = list.iter();
+    to_wrong_expr(consume_exact(iter.next(), LispVal::symbol("define-values")))?;
+    let formals = consume(iter.next(), "Expect formals")?;
+    let v = consume(iter.next(), "Expect expression").map(|a| eval(&a, env))??;
+    nothing_to_consume(iter.next())?;
+    bind_values(&formals, v, env)?;
+    Ok(LispVal::Unspecified)
+}
+
+/// `(receive formals expr body ...)`
+fn receive(list: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    let mut iter = list.iter();
+    to_wrong_expr(consume_exact(iter.next(), LispVal::symbol("receive")))?;
+    let formals = consume(iter.next(), "Expect formals")?;
+    let v = consume(iter.next(), "Expect expression").map(|a| eval(&a, env))??;
+    let scope = Rc::new(RefCell::new(Env::child(env.clone())));
+    bind_values(&formals, v, &scope)?;
+    eval_body(iter.as_slice(), &scope)
+}
+
+/// `(let-values ((formals expr) ...) body ...)` and `let*-values`, where each expression
+/// also sees the bindings before it.
+fn let_values(list: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    let mut iter = list.iter();
+    let sequential = match iter.next() {
+        Some(LispVal::Symbol(s)) if &*s.name() == "let-values" => false,
+        Some(LispVal::Symbol(s)) if &*s.name() == "let*-values" => true,
+        _ => return Err(WrongExpression("Expected let-values".to_string())),
+    };
+    let bindings = consume_list(iter.next())?;
+    let scope = Rc::new(RefCell::new(Env::child(env.clone())));
+    for binding in bindings {
+        let binding = binding.to_vec()?;
+        let [formals, expr] = binding.as_slice() else {
+            return Err(Runtime(format!("Expected (formals expression) but got {}", LispVal::list(binding))));
+        };
+        let v = eval(expr, if sequential { &scope } else { env })?;
+        bind_values(formals, v, &scope)?;
+    }
+    eval_body(iter.as_slice(), &scope)
+}
+
+fn promise(expr: LispVal, env: &Rc<RefCell<Env>>, lazy: bool) -> LispVal {
+    LispVal::Promise(Rc::new(Promise::new(PromiseState::Delayed { expr, env: env.clone(), lazy })))
+}
+
+/// `(delay expr)` and `(delay-force expr)`, where `expr` evaluates to a promise.
+fn delay(list: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    let mut iter = list.iter();
+    let lazy = match iter.next() {
+        Some(LispVal::Symbol(s)) if &*s.name() == "delay" => false,
+        Some(LispVal::Symbol(s)) if &*s.name() == "delay-force" => true,
+        _ => return Err(WrongExpression("Expected delay".to_string())),
+    };
+    let expr = consume(iter.next(), "Expect expression")?;
+    nothing_to_consume(iter.next())?;
+    Ok(promise(expr, env, lazy))
+}
+
+/// `(stream-cons a s)` is a stream whose car is `a` and whose cdr is the stream `s`,
+/// neither evaluated until needed: `(delay (cons (delay a) (delay-force s)))`.
+fn stream_cons(list: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    let mut iter = list.iter();
+    to_wrong_expr(consume_exact(iter.next(), LispVal::symbol("stream-cons")))?;
+    let car = consume(iter.next(), "Expect stream car")?;
+    let cdr = consume(iter.next(), "Expect stream cdr")?;
+    nothing_to_consume(iter.next())?;
+    let pair = LispVal::list(vec![
+        LispVal::symbol("cons"),
+        LispVal::list(vec![LispVal::symbol("delay"), car]),
+        LispVal::list(vec![LispVal::symbol("delay-force"), cdr]),
+    ]);
+    Ok(promise(pair, env, false))
+}
+
+/// `(parameterize ((param value) ...) body ...)` evaluates every parameter and value
+/// first, then runs the body with the converted values bound.
+fn parameterize(list: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    let mut iter = list.iter();
+    to_wrong_expr(consume_exact(iter.next(), LispVal::symbol("parameterize")))?;
+    let mut bindings = vec![];
+    for binding in consume_list(iter.next())? {
+        let binding = binding.to_vec()?;
+        let [param, value] = binding.as_slice() else {
+            return Err(Runtime(format!("Expected (parameter value) but got {}", LispVal::list(binding))));
+        };
+        bindings.push((parameter(&eval(param, env)?)?, eval(value, env)?));
+    }
+    let bindings = bindings.into_iter()
+        .map(|(p, v)| p.convert(v, env).map(|v| (p, v)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let scope = Rc::new(RefCell::new(Env::child(env.clone())));
+    with_parameters(bindings, || eval_body(iter.as_slice(), &scope))
+}
+
+/// `(begin form ...)` evaluates the forms in the enclosing scope, so definitions in it
+/// are spliced into the surrounding body or top level.
+fn begin(list: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    to_wrong_expr(consume_exact(list.first(), LispVal::symbol("begin")))?;
+    eval_sequence(&list[1..], env)
+}
+
+fn eval_sequence(forms: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    let mut result = LispVal::Unspecified;
+    for form in forms {
+        result = eval(form, env)?;
+    }
+    Ok(result)
+}
+
+/// Evaluates a loop test, which like an `if` condition must be a boolean.
+fn test(v: &LispVal, env: &Rc<RefCell<Env>>) -> Result<bool, LispErr> {
+    match eval(v, env)? {
+        Boolean(b) => Ok(b),
+        condition => Err(Runtime(format!("Expected boolean condition {}", condition))),
+    }
+}
+
+/// `(do ((var init step) ...) (test result ...) command ...)`. Every iteration binds the
+/// variables afresh, so closures made in one iteration keep its values.
+fn eval_do(list: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    let mut iter = list.iter();
+    to_wrong_expr(consume_exact(iter.next(), LispVal::symbol("do")))?;
+    let mut vars = vec![];
+    let mut values = vec![];
+    let mut steps = vec![];
+    for spec in consume_list(iter.next())? {
+        let spec = spec.to_vec()?;
+        let (var, init, step) = match spec.as_slice() {
+            [var, init] => (var, init, None),
+            [var, init, step] => (var, init, Some(step)),
+            _ => return Err(Runtime(format!("Expected (variable init [step]) but got {}", LispVal::list(spec)))),
+        };
+        vars.push(extract_symbol(Ok(var.clone()))?);
+        values.push(eval(init, env)?);
+        steps.push(step.cloned());
+    }
+    let exit = consume_list(iter.next())?;
+    let (exit_test, result) = exit.split_first().ok_or_else(|| Runtime("Expected (test result ...)".to_string()))?;
+    let commands = iter.as_slice();
+    loop {
+        let scope = Rc::new(RefCell::new(Env::child(env.clone())));
+        for (var, v) in vars.iter().zip(values.drain(..)) {
+            scope.borrow_mut().define(*var, v)?;
+        }
+        if test(exit_test, &scope)? {
+            return eval_sequence(result, &scope);
+        }
+        eval_sequence(commands, &scope)?;
+        for (var, step) in vars.iter().zip(&steps) {
+            values.push(match step {
+                Some(step) => eval(step, &scope)?,
+                None => get_var(*var, &scope)?,
+            });
         }
     }
-    result
 }
 
-fn evaluate_if(list: &Vec<LispVal>, env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+/// `(while test body ...)`
+fn eval_while(list: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
     let mut iter = list.iter();
-    to_wrong_expr(consume_exact(iter.next(), Atom("if".to_string())))?;
+    to_wrong_expr(consume_exact(iter.next(), LispVal::symbol("while")))?;
+    let condition = consume(iter.next(), "Expect condition")?;
+    while test(&condition, env)? {
+        eval_sequence(iter.as_slice(), env)?;
+    }
+    Ok(LispVal::Unspecified)
+}
+
+/// The variable, the evaluated value and the optional result expression of a
+/// `(var value [result])` loop spec.
+fn loop_spec(spec: Option<&LispVal>, env: &Rc<RefCell<Env>>) -> Result<(Symbol, LispVal, Option<LispVal>), LispErr> {
+    let spec = consume_list(spec)?;
+    match spec.as_slice() {
+        [var, value] => Ok((extract_symbol(Ok(var.clone()))?, eval(value, env)?, None)),
+        [var, value, result] => Ok((extract_symbol(Ok(var.clone()))?, eval(value, env)?, Some(result.clone()))),
+        _ => Err(Runtime(format!("Expected (variable value [result]) but got {}", LispVal::list(spec)))),
+    }
+}
+
+/// Runs `body` once for each of `items` with `var` bound to it in a fresh scope, then
+/// evaluates `result` with `var` bound to `last`.
+fn iterate(var: Symbol, items: impl Iterator<Item=LispVal>, last: LispVal, result: Option<LispVal>,
+           body: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    for item in items {
+        let scope = Rc::new(RefCell::new(Env::child(env.clone())));
+        scope.borrow_mut().define(var, item)?;
+        eval_sequence(body, &scope)?;
+    }
+    match result {
+        Some(result) => {
+            let scope = Rc::new(RefCell::new(Env::child(env.clone())));
+            scope.borrow_mut().define(var, last)?;
+            eval(&result, &scope)
+        }
+        None => Ok(LispVal::Unspecified),
+    }
+}
+
+/// `(dotimes (var count [result]) body ...)` runs the body with `var` from 0 to
+/// `count - 1`; `result` sees `var` bound to `count`.
+fn dotimes(list: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    let mut iter = list.iter();
+    to_wrong_expr(consume_exact(iter.next(), LispVal::symbol("dotimes")))?;
+    let (var, count, result) = loop_spec(iter.next(), env)?;
+    let n = count.num()?;
+    iterate(var, (0..n).map(LispVal::Number), LispVal::Number(n.max(0)), result, iter.as_slice(), env)
+}
+
+/// `(dolist (var list [result]) body ...)` runs the body with `var` bound to each element
+/// of the list; `result` sees `var` bound to `()`.
+fn dolist(list: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    let mut iter = list.iter();
+    to_wrong_expr(consume_exact(iter.next(), LispVal::symbol("dolist")))?;
+    let (var, items, result) = loop_spec(iter.next(), env)?;
+    iterate(var, items.to_vec()?.into_iter(), LispVal::Nil, result, iter.as_slice(), env)
+}
+
+fn evaluate_if(list: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    let mut iter = list.iter();
+    to_wrong_expr(consume_exact(iter.next(), LispVal::symbol("if")))?;
     let condition = consume(iter.next(), "Expect condition ").map(|a| eval(&a, env))??;
     let left = consume(iter.next(), "Expect expression ")?;
     let right = consume(iter.next(), "Expect expression ")?;
@@ -230,9 +705,169 @@ fn evaluate_if(list: &Vec<LispVal>, env: &Rc<RefCell<Env>>) -> Result<LispVal, L
     }
 }
 
+/// Turns an error into `WrongExpression`, so `eval_any_of` tries the next form. Every
+/// call tries most forms, so the message is not formatted: it would be discarded anyway.
 fn to_wrong_expr(r: Result<LispVal, LispErr>) -> Result<LispVal, LispErr> {
     match r {
         Ok(_) => r,
-        Err(e) => Err(WrongExpression(e.to_string())),
+        Err(_) => Err(WrongExpression(String::new())),
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+use crate::parser::parse_expr;
+#[cfg(test)]
+use crate::primitive_functions::create_eden_env;
+
+#[cfg(test)]
+pub fn eval_str(s: &str, env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    let (_, e) = parse_expr(s).unwrap();
+    eval(&e, env)
+}
+
+#[test]
+fn eval_test() {
+    let env = &create_eden_env();
+    let (_, e) = parse_expr("(+ 2 \"3\")").unwrap();
+    println!("Expression input: {}", e);
+    let res = eval(&e, env).unwrap();
+    println!("Expression input: {}", res);
+    assert_eq!(res.to_string(), "5");
+
+    let (_, e) = parse_expr("(- (+ 4 6 3) 3 5 2)").unwrap();
+    println!("Expression input: {}", e);
+    let res = eval(&e, env).unwrap();
+    println!("Expression input: {}", res);
+    assert_eq!(res.to_string(), "3");
+
+    let (_, e) = parse_expr("(+ 2 (- 4 1))").unwrap();
+    println!("Expression input: {}", e);
+    let res = eval(&e, env).unwrap();
+    println!("Expression input: {}", res);
+    assert_eq!(res.to_string(), "5");
+}
+#[test]
+fn eval_test2() {
+    let env = &create_eden_env();
+    let (_, e) = parse_expr("(define a 2)").unwrap();
+    println!("Expression input: {}", e);
+    let res = eval(&e, env).unwrap();
+    println!("Expression result: {}", res);
+    assert_eq!(res.to_string(), "2");
+
+    let (_, e) = parse_expr("a").unwrap();
+    println!("Expression input: {}", e);
+    let res = eval(&e, env).unwrap();
+    println!("Expression result: {}", res);
+    assert_eq!(res.to_string(), "2");
+}
+
+#[test]
+fn pair_test() {
+    let env = &create_eden_env();
+    let run = |s: &str| eval(&parse_expr(s).unwrap().1, env).unwrap().to_string();
+    run("(define a '(1 2 3))");
+    run("(define b (cdr a))");
+    run("(set-car! b 20)");
+    assert_eq!(run("a"), "(1 20 3)");
+    run("(set-cdr! (cdr b) 4)");
+    assert_eq!(run("a"), "(1 20 3 . 4)");
+    assert_eq!(run("(eq? (cdr a) b)"), "#t");
+    assert_eq!(run("(eq? (cons 1 2) (cons 1 2))"), "#f");
+    assert_eq!(run("(equal? (cons 1 2) (cons 1 2))"), "#t");
+}
+
+#[test]
+fn circular_list_test() {
+    let env = &create_eden_env();
+    let run = |s: &str| eval(&parse_expr(s).unwrap().1, env);
+    run("(define c (cons 1 (cons 2 '())))").unwrap();
+    run("(set-cdr! (cdr c) c)").unwrap();
+    assert_eq!(run("(car (cdr (cdr (cdr c))))").unwrap().to_string(), "2");
+    assert_eq!(run("(eq? c (cdr (cdr c)))").unwrap().to_string(), "#t");
+    assert!(run("(apply + c)").is_err());
+    run("(define a (list 1))").unwrap();
+    run("(set-cdr! a a)").unwrap();
+    run("(define b (list 1 1))").unwrap();
+    run("(set-cdr! (cdr b) b)").unwrap();
+    run("(define d (list 1 2))").unwrap();
+    run("(set-cdr! (cdr d) d)").unwrap();
+    assert_eq!(run("(list (equal? a b) (equal? b a) (equal? a d) (equal? c d) (eqv? a b))").unwrap().to_string(), "(#t #t #f #t #f)");
+    run("(define nested (list a 2))").unwrap();
+    assert_eq!(run("(equal? nested (list b 2))").unwrap().to_string(), "#t");
+    assert!(crate::lispval::equal(&run("a").unwrap(), &run("b").unwrap()));
+    assert_ne!(run("a").unwrap(), run("b").unwrap());
+    assert_eq!(run("a").unwrap(), run("(cdr a)").unwrap());
+}
+
+#[test]
+fn lambda_list_test() {
+    let env = &create_eden_env();
+    let run = |s: &str| eval(&parse_expr(s).unwrap().1, env);
+    let show = |s: &str| run(s).unwrap().to_string();
+    assert_eq!(show("((lambda args args) 1 2)"), "(1 2)");
+    assert_eq!(show("((lambda (a . rest) (list a rest)) 1 2 3)"), "(1 (2 3))");
+    run("(define (f a . rest) rest)").unwrap();
+    assert_eq!(show("(f 1)"), "()");
+    run("(define (opt a #!optional (b (+ a 1)) c) (list a b c))").unwrap();
+    assert_eq!(show("(opt 1)"), "(1 2 #f)");
+    assert_eq!(show("(opt 1 5 6)"), "(1 5 6)");
+    assert!(run("(opt 1 2 3 4)").is_err());
+    run("(define (key a #!optional b #!key (size 10) color) (list a b size color))").unwrap();
+    assert_eq!(show("(key 1)"), "(1 #f 10 #f)");
+    assert_eq!(show("(key 1 2 color: 'red)"), "(1 2 10 red)");
+    assert_eq!(show("(key 1 size: 3)"), "(1 #f 3 #f)");
+    assert!(run("(key 1 weight: 3)").is_err());
+    assert!(run("(key 1 size:)").is_err());
+    assert!(run("size:").is_err());
+    assert!(run("(list color: 1)").is_err());
+    assert!(run("(key color: 1)").is_err());
+    run("(define size: 7)").unwrap();
+    assert_eq!(show("(key size: 2)"), "(7 2 10 #f)");
+    assert_eq!(show("((lambda (#!rest r #!key k) (list r k)) k: 1 other: 2)"), "((k: 1 other: 2) 1)");
+    run("(define area (case-lambda ((r) (* 3 r r)) ((w h) (* w h)) ((w h . more) more)))").unwrap();
+    assert_eq!(show("(area 2)"), "12");
+    assert_eq!(show("(area 2 3)"), "6");
+    assert_eq!(show("(area 2 3 4)"), "(4)");
+    assert_eq!(show("area"), "#<procedure area>");
+    assert!(run("((case-lambda ((a) a)))").is_err());
+}
+
+#[test]
+fn internal_definition_test() {
+    let env = &create_eden_env();
+    let run = |s: &str| eval(&parse_expr(s).unwrap().1, env);
+    let show = |s: &str| run(s).unwrap().to_string();
+    run("(define (parity n) (define (even? n) (if (= n 0) #t (odd? (- n 1)))) (define (odd? n) (if (= n 0) #f (even? (- n 1)))) (even? n))").unwrap();
+    assert_eq!(show("(parity 10)"), "#t");
+    assert_eq!(show("(parity 7)"), "#f");
+    run("(define x 1)").unwrap();
+    assert!(run("((lambda () (define y x) (define x 2) y))").is_err());
+    assert_eq!(show("((lambda () (define (get) x) (define x 2) (get)))"), "2");
+    assert_eq!(show("((lambda () (begin (define a 1) (define-values (b c) (values 2 3))) (list a b c)))"), "(1 2 3)");
+    assert!(run("((lambda () (display \"\") (define z 1) z))").is_err());
+    assert_eq!(show("x"), "1");
+    assert_eq!(show("(begin (define w 5) (+ w 1))"), "6");
+    assert_eq!(show("w"), "5");
+    assert_eq!(show("(begin)"), "#<unspecified>");
+}
+
+#[test]
+fn iteration_test() {
+    let env = &create_eden_env();
+    let run = |s: &str| eval(&parse_expr(s).unwrap().1, env);
+    let show = |s: &str| run(s).unwrap().to_string();
+    assert_eq!(show("(do ((i 0 (+ i 1)) (acc '() (cons i acc))) ((= i 4) acc))"), "(3 2 1 0)");
+    assert_eq!(show("(do ((i 0 (+ i 1)) (fs '() (cons (lambda () i) fs))) ((= i 3) (map (lambda (f) (f)) fs)))"), "(2 1 0)");
+    assert_eq!(show("(do ((n 0)) (#t n))"), "0");
+    run("(define n 0)").unwrap();
+    run("(while (< n 50000) (set! n (+ n 1)))").unwrap();
+    assert_eq!(show("n"), "50000");
+    run("(define total 0)").unwrap();
+    assert_eq!(show("(dotimes (i 5 (list i total)) (set! total (+ total i)))"), "(5 10)");
+    run("(define seen '())").unwrap();
+    run("(dolist (x '(a b c)) (set! seen (cons x seen)))").unwrap();
+    assert_eq!(show("seen"), "(c b a)");
+    assert_eq!(show("(dolist (x '(1 2) x))"), "()");
+    assert!(run("(while 1 (set! n 0))").is_err());
+    assert!(run("(dolist (x 5) x)").is_err());
+}
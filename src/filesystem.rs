@@ -0,0 +1,94 @@
+use std::cell::RefCell;
+use std::fs;
+use std::rc::Rc;
+
+use crate::env::Env;
+use crate::error::{io_error, LispErr};
+use crate::lispval::LispVal;
+use crate::lispval::LispVal::{Boolean, LispString, Unspecified};
+use crate::primitive_functions::expect_args;
+
+fn file_exists(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 1)?;
+    Ok(Boolean(fs::metadata(a[0].str()?).is_ok()))
+}
+
+fn delete_file(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 1)?;
+    let path = a[0].str()?;
+    fs::remove_file(&path).map_err(|e| io_error(&path, e))?;
+    Ok(Unspecified)
+}
+
+fn rename_file(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 2)?;
+    let from = a[0].str()?;
+    fs::rename(&from, a[1].str()?).map_err(|e| io_error(&from, e))?;
+    Ok(Unspecified)
+}
+
+fn create_directory(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 1)?;
+    let path = a[0].str()?;
+    fs::create_dir(&path).map_err(|e| io_error(&path, e))?;
+    Ok(Unspecified)
+}
+
+fn delete_directory(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 1)?;
+    let path = a[0].str()?;
+    fs::remove_dir(&path).map_err(|e| io_error(&path, e))?;
+    Ok(Unspecified)
+}
+
+fn file_directory(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 1)?;
+    Ok(Boolean(fs::metadata(a[0].str()?).is_ok_and(|m| m.is_dir())))
+}
+
+/// `(directory-files path)` lists the entry names of a directory, sorted so the result
+/// doesn't depend on the file system.
+fn directory_files(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 1)?;
+    let path = a[0].str()?;
+    let mut names = fs::read_dir(&path)
+        .and_then(|entries| entries.map(|e| Ok(e?.file_name().to_string_lossy().into_owned())).collect::<Result<Vec<_>, _>>())
+        .map_err(|e| io_error(&path, e))?;
+    names.sort();
+    Ok(LispVal::list(names.into_iter().map(LispString).collect()))
+}
+
+pub fn define_filesystem_functions(e: &mut Env) {
+    e.define_primitive("file-exists?", file_exists).unwrap();
+    e.define_primitive("delete-file", delete_file).unwrap();
+    e.define_primitive("rename-file", rename_file).unwrap();
+    e.define_primitive("create-directory", create_directory).unwrap();
+    e.define_primitive("delete-directory", delete_directory).unwrap();
+    e.define_primitive("file-directory?", file_directory).unwrap();
+    e.define_primitive("directory-files", directory_files).unwrap();
+}
+
+#[test]
+fn filesystem_test() {
+    use crate::evaluation::eval_str;
+    let env = &crate::primitive_functions::create_eden_env();
+    let dir = std::env::temp_dir().join(format!("lisp-filesystem-test-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    for (name, path) in [("dir", dir.clone()), ("a", dir.join("a")), ("b", dir.join("b"))] {
+        env.borrow_mut().define(name, LispString(path.to_string_lossy().into_owned())).unwrap();
+    }
+    let run = |s: &str| eval_str(s, env).unwrap().to_string();
+    run("(create-directory dir)");
+    assert_eq!(run("(file-directory? dir)"), "#t");
+    run("(call-with-output-file a (lambda (p) (write-string \"abc\" p)))");
+    assert_eq!(run("(file-exists? a)"), "#t");
+    run("(rename-file a b)");
+    run("(with-output-to-file a (lambda () (display 1)))");
+    assert_eq!(run("(directory-files dir)"), "(\"a\" \"b\")");
+    run("(delete-file a)");
+    assert_eq!(run("(file-exists? a)"), "#f");
+    assert!(matches!(eval_str("(delete-file a)", env), Err(LispErr::Io(..))));
+    run("(delete-file b)");
+    run("(delete-directory dir)");
+    assert_eq!(run("(file-exists? dir)"), "#f");
+}
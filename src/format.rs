@@ -0,0 +1,177 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::env::Env;
+use crate::error::LispErr;
+use crate::error::LispErr::Runtime;
+use crate::lispval::LispVal;
+use crate::lispval::LispVal::{Boolean, LispString, Unspecified};
+use crate::port::current_output;
+use crate::pretty::{pretty_string, pretty_width};
+use crate::printer::{print_string, Mode};
+
+/// Largest width or digit count a directive may ask for.
+const MAX_WIDTH: usize = 4096;
+
+/// A numeric directive parameter, or `None` if it was left out.
+fn parameter(digits: &str) -> Result<Option<usize>, LispErr> {
+    if digits.is_empty() {
+        return Ok(None);
+    }
+    match digits.parse::<usize>() {
+        Ok(n) if n <= MAX_WIDTH => Ok(Some(n)),
+        _ => Err(Runtime(format!("format: parameter {} is larger than {}", digits, MAX_WIDTH))),
+    }
+}
+
+fn pad_left(s: String, width: Option<usize>) -> String {
+    match width {
+        Some(w) if s.chars().count() < w => format!("{}{}", " ".repeat(w - s.chars().count()), s),
+        _ => s,
+    }
+}
+
+fn pad_right(s: String, width: Option<usize>) -> String {
+    match width {
+        Some(w) if s.chars().count() < w => format!("{}{}", s, " ".repeat(w - s.chars().count())),
+        _ => s,
+    }
+}
+
+fn integer(v: &LispVal, radix: u32) -> Result<String, LispErr> {
+    let n = v.num()?;
+    let digits = match radix {
+        2 => format!("{:b}", n.unsigned_abs()),
+        8 => format!("{:o}", n.unsigned_abs()),
+        16 => format!("{:x}", n.unsigned_abs()),
+        _ => n.unsigned_abs().to_string(),
+    };
+    Ok(if n < 0 { format!("-{}", digits) } else { digits })
+}
+
+/// `~w,dF`: numbers have no fractional part, so `d` only adds zeros after the point.
+fn fixed(v: &LispVal, digits: Option<usize>) -> Result<String, LispErr> {
+    match (v, digits) {
+        (LispString(s), _) => Ok(s.clone()),
+        (_, Some(d)) if d > 0 => Ok(format!("{}.{}", v.num()?, "0".repeat(d))),
+        _ => Ok(v.num()?.to_string()),
+    }
+}
+
+/// Expands the SRFI-48 directives in `control`:
+///
+/// * `~a` `~s` `~w` display, write and write with shared structure, `~y` pretty print
+/// * `~d` `~x` `~o` `~b` integers in base 10, 16, 8 and 2, `~c` a character
+/// * `~w,dF` a number `w` columns wide with `d` digits after the point
+/// * `~%` `~n` newline, `~&` newline unless at the start of a line, `~_` space, `~~` tilde
+/// * `~?` and `~k` format the next two arguments as a control string and its arguments
+///
+/// A width before `a`, `s`, `w`, `d`, `x`, `o` or `b` pads the output to that many columns,
+/// text on the right and numbers on the left. `line_start` tells `~&` whether the
+/// destination is at the start of a line before any of the output.
+pub fn format_string(control: &str, args: &[LispVal], line_start: bool) -> Result<String, LispErr> {
+    let mut out = String::new();
+    let mut args = args.iter();
+    let mut chars = control.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '~' {
+            out.push(c);
+            continue;
+        }
+        let mut params = vec![];
+        let mut number = String::new();
+        let directive = loop {
+            match chars.next() {
+                Some(d) if d.is_ascii_digit() => number.push(d),
+                Some(',') => params.push(parameter(&std::mem::take(&mut number))?),
+                Some(d) => break d,
+                None => return Err(Runtime("format: control string ends in ~".to_string())),
+            }
+        };
+        params.push(parameter(&number)?);
+        let width = params[0];
+        let mut next = || args.next().ok_or_else(|| Runtime(format!("format: too few arguments for {}", control)));
+        match directive.to_ascii_lowercase() {
+            'a' => out.push_str(&pad_right(print_string(next()?, Mode::Display), width)),
+            's' => out.push_str(&pad_right(print_string(next()?, Mode::Write), width)),
+            'w' => out.push_str(&pad_right(print_string(next()?, Mode::WriteShared), width)),
+            'y' => out.push_str(&pretty_string(next()?, pretty_width())),
+            'd' => out.push_str(&pad_left(integer(next()?, 10)?, width)),
+            'x' => out.push_str(&pad_left(integer(next()?, 16)?, width)),
+            'o' => out.push_str(&pad_left(integer(next()?, 8)?, width)),
+            'b' => out.push_str(&pad_left(integer(next()?, 2)?, width)),
+            'f' => out.push_str(&pad_left(fixed(next()?, params.get(1).copied().flatten())?, width)),
+            'c' => match next()? {
+                LispVal::Char(c) => out.push(*c),
+                v => return Err(Runtime(format!("format: expected character but got {}", v))),
+            },
+            '%' | 'n' => out.push('\n'),
+            '&' => if !(out.is_empty() && line_start || out.ends_with('\n')) { out.push('\n') },
+            '_' => out.push(' '),
+            '~' => out.push('~'),
+            '?' | 'k' => {
+                let control = next()?.str()?;
+                let nested = next()?.to_vec()?;
+                let nested_line_start = out.is_empty() && line_start || out.ends_with('\n');
+                out.push_str(&format_string(&control, &nested, nested_line_start)?);
+            }
+            d => return Err(Runtime(format!("format: unknown directive ~{}", d))),
+        }
+    }
+    if args.next().is_some() {
+        return Err(Runtime(format!("format: too many arguments for {}", control)));
+    }
+    Ok(out)
+}
+
+/// `(format [destination] control arg ...)` returns the text when the destination is
+/// missing or `#f`, and otherwise writes it to the port, or the current output for `#t`.
+fn format(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    let port = match a.first() {
+        Some(LispString(_)) => return Ok(LispString(format_string(&a[0].str()?, &a[1..], true)?)),
+        Some(Boolean(false)) => None,
+        Some(Boolean(true)) => Some(current_output()),
+        Some(LispVal::Port(p)) => Some(p.clone()),
+        Some(v) => return Err(Runtime(format!("format: bad destination {}", v))),
+        None => return Err(Runtime("format: expected control string".to_string())),
+    };
+    let control = a.get(1).ok_or_else(|| Runtime("format: expected control string".to_string()))?.str()?;
+    let s = format_string(&control, &a[2..], port.as_ref().is_none_or(|p| p.at_line_start()))?;
+    match port {
+        Some(p) => {
+            p.write_str(&s)?;
+            Ok(Unspecified)
+        }
+        None => Ok(LispString(s)),
+    }
+}
+
+pub fn define_format_functions(e: &mut Env) {
+    e.define_primitive("format", format).unwrap();
+}
+
+#[test]
+fn format_test() {
+    use crate::evaluation::eval_str;
+    let env = &crate::primitive_functions::create_eden_env();
+    let run = |s: &str| eval_str(s, env).unwrap().str().unwrap();
+    assert_eq!(run("(format \"~a and ~s\" \"x\" \"y\")"), "x and \"y\"");
+    assert_eq!(run("(format #f \"~d ~x ~o ~b\" 255 255 -8 5)"), "255 ff -10 101");
+    assert_eq!(run("(format #f \"[~5a][~5d][~6,2f]\" 'ab 42 7)"), "[ab   ][   42][  7.00]");
+    assert_eq!(run("(format #f \"a~%b~&~&c~~~_~c\" #\\z)"), "a\nb\nc~ z");
+    assert_eq!(run("(format #f \"<~?>\" \"~a-~a\" '(1 2))"), "<1-2>");
+    assert_eq!(run("(format #f \"~s\" '(1 \"two\" #\\3))"), "(1 \"two\" #\\3)");
+    assert_eq!(run("(call-with-output-string (lambda (p) (format p \"~a\" 1)))"), "1");
+    assert!(eval_str("(format #f \"~a ~a\" 1)", env).is_err());
+    assert!(eval_str("(format #f \"~a\" 1 2)", env).is_err());
+    assert!(eval_str("(format #f \"~q\" 1)", env).is_err());
+    assert!(eval_str("(format #f \"~10000000000000000000a\" 1)", env).is_err());
+    assert!(eval_str("(format #f \"~5000a\" 1)", env).is_err());
+    assert!(eval_str("(format #f \"~1,9999f\" 1)", env).is_err());
+    assert_eq!(run("(format #f \"~4096a\" 1)").len(), 4096);
+    eval_str("(define p (open-output-string))", env).unwrap();
+    eval_str("(format p \"~&a\")", env).unwrap();
+    eval_str("(format p \"~&b~%\")", env).unwrap();
+    eval_str("(format p \"~&c~&\")", env).unwrap();
+    assert_eq!(run("(get-output-string p)"), "a\nb\nc\n");
+}
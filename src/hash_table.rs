@@ -0,0 +1,334 @@
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::mem::discriminant;
+use std::rc::Rc;
+
+use crate::env::Env;
+use crate::error::LispErr;
+use crate::error::LispErr::Runtime;
+use crate::evaluation::call_function;
+use crate::lispval::{equal, eqv, LispVal, PrimitiveFn};
+use crate::lispval::LispVal::{Boolean, Number, PrimitiveFunc, Unspecified};
+use crate::primitive_functions::{expect_args, expect_args_between};
+
+/// Key comparison used by a hash table, picked by the procedure given to `make-hash-table`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Equivalence {
+    Equal,
+    Eqv,
+}
+
+#[derive(Clone, Debug)]
+struct HashKey {
+    value: LispVal,
+    equivalence: Equivalence,
+}
+
+impl PartialEq for HashKey {
+    fn eq(&self, other: &Self) -> bool {
+        match self.equivalence {
+            Equivalence::Equal => equal(&self.value, &other.value),
+            Equivalence::Eqv => eqv(&self.value, &other.value),
+        }
+    }
+}
+
+impl Eq for HashKey {}
+
+impl Hash for HashKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_value(&self.value, self.equivalence, state)
+    }
+}
+
+/// Upper bound on the number of pairs visited when hashing a structure, so that circular
+/// lists hash in finite time. Equal structures still hash identically.
+const HASH_DEPTH: usize = 64;
+
+/// Feeds `v` into `state` so that values equivalent under `equivalence` hash identically.
+/// Procedures only contribute their kind, values with identity contribute their address.
+pub fn hash_value<H: Hasher>(v: &LispVal, equivalence: Equivalence, state: &mut H) {
+    let mut budget = HASH_DEPTH;
+    hash_bounded(v, equivalence, state, &mut budget);
+}
+
+fn hash_bounded<H: Hasher>(v: &LispVal, equivalence: Equivalence, state: &mut H, budget: &mut usize) {
+    discriminant(v).hash(state);
+    match v {
+        LispVal::Symbol(s) => s.hash(state),
+        LispVal::Number(n) => n.hash(state),
+        LispVal::LispString(s) => s.hash(state),
+        LispVal::Char(c) => c.hash(state),
+        LispVal::Boolean(b) => b.hash(state),
+        LispVal::Nil | LispVal::Eof | LispVal::Unspecified => (),
+        LispVal::Pair(p) if equivalence == Equivalence::Eqv => Rc::as_ptr(p).hash(state),
+        LispVal::Pair(p) => {
+            if *budget == 0 {
+                return;
+            }
+            *budget -= 1;
+            hash_bounded(&p.car.borrow(), equivalence, state, budget);
+            hash_bounded(&p.cdr.borrow(), equivalence, state, budget);
+        }
+        LispVal::Quote(q) => hash_bounded(q, equivalence, state, budget),
+        LispVal::Func { .. } | LispVal::CaseLambda { .. } | LispVal::PrimitiveFunc { .. } => (),
+        LispVal::NativeFunc(f) => Rc::as_ptr(f).hash(state),
+        LispVal::HashTable(t) => Rc::as_ptr(t).hash(state),
+        LispVal::Port(p) => Rc::as_ptr(p).hash(state),
+        LispVal::Environment(e) => Rc::as_ptr(e).hash(state),
+        LispVal::RecordType(t) => Rc::as_ptr(t).hash(state),
+        LispVal::Record(r) => Rc::as_ptr(r).hash(state),
+        LispVal::Promise(p) => Rc::as_ptr(p).hash(state),
+        LispVal::Parameter(p) => Rc::as_ptr(p).hash(state),
+        LispVal::Values(vs) => vs.iter().for_each(|v| hash_bounded(v, equivalence, state, budget)),
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct HashTable {
+    equivalence: Equivalence,
+    entries: HashMap<HashKey, LispVal>,
+}
+
+impl HashTable {
+    pub fn new(equivalence: Equivalence) -> Self {
+        HashTable { equivalence, entries: HashMap::new() }
+    }
+
+    fn key(&self, key: &LispVal) -> HashKey {
+        HashKey { value: key.clone(), equivalence: self.equivalence }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &LispVal) -> Option<LispVal> {
+        self.entries.get(&self.key(key)).cloned()
+    }
+
+    pub fn insert(&mut self, key: LispVal, value: LispVal) {
+        let key = self.key(&key);
+        self.entries.insert(key, value);
+    }
+
+    pub fn remove(&mut self, key: &LispVal) -> Option<LispVal> {
+        let key = self.key(key);
+        self.entries.remove(&key)
+    }
+
+    pub fn entries(&self) -> Vec<(LispVal, LispVal)> {
+        self.entries.iter().map(|(k, v)| (k.value.clone(), v.clone())).collect()
+    }
+}
+
+fn table(v: &LispVal) -> Result<Rc<RefCell<HashTable>>, LispErr> {
+    match v {
+        LispVal::HashTable(t) => Ok(t.clone()),
+        _ => Err(Runtime(format!("Expected hash table but got {}", v))),
+    }
+}
+
+fn is_primitive(v: &LispVal, f: PrimitiveFn) -> bool {
+    matches!(v, PrimitiveFunc { func, .. } if std::ptr::fn_addr_eq(*func, f))
+}
+
+fn make_hash_table(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args_between(a, 0, 2)?;
+    let equivalence = match a.first() {
+        None => Equivalence::Equal,
+        Some(f) if is_primitive(f, crate::primitive_functions::equal) => Equivalence::Equal,
+        Some(f) if is_primitive(f, crate::primitive_functions::eqv) => Equivalence::Eqv,
+        Some(f) => return Err(Runtime(format!("Unsupported hash table equivalence {}", f))),
+    };
+    Ok(LispVal::HashTable(Rc::new(RefCell::new(HashTable::new(equivalence)))))
+}
+
+fn is_hash_table(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 1)?;
+    Ok(Boolean(matches!(a[0], LispVal::HashTable(_))))
+}
+
+/// `(hash-table-ref table key [failure [success]])`
+fn hash_table_ref(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args_between(a, 2, 4)?;
+    let found = table(&a[0])?.borrow().get(&a[1]);
+    match (found, a.get(2)) {
+        (Some(v), _) => match a.get(3) {
+            Some(success) => call_function(success, &[v], env),
+            None => Ok(v),
+        },
+        (None, Some(failure)) => call_function(failure, &[], env),
+        (None, None) => Err(Runtime(format!("Key {} not found in hash table", a[1]))),
+    }
+}
+
+fn hash_table_ref_default(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 3)?;
+    Ok(table(&a[0])?.borrow().get(&a[1]).unwrap_or_else(|| a[2].clone()))
+}
+
+fn hash_table_set(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 3)?;
+    table(&a[0])?.borrow_mut().insert(a[1].clone(), a[2].clone());
+    Ok(a[2].clone())
+}
+
+/// `(hash-table-delete! table key ...)` returns the number of keys actually removed.
+fn hash_table_delete(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    if a.is_empty() {
+        return Err(Runtime("Expected at least 1 argument".to_string()));
+    }
+    let t = table(&a[0])?;
+    let mut t = t.borrow_mut();
+    let removed = a[1..].iter().filter(|k| t.remove(k).is_some()).count();
+    Ok(Number(removed as i64))
+}
+
+fn hash_table_contains(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 2)?;
+    Ok(Boolean(table(&a[0])?.borrow().get(&a[1]).is_some()))
+}
+
+/// `(hash-table-update! table key updater [failure [success]])`
+fn hash_table_update(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args_between(a, 3, 5)?;
+    let mut ref_args = vec![a[0].clone(), a[1].clone()];
+    ref_args.extend_from_slice(&a[3..]);
+    let current = hash_table_ref(&ref_args, env)?;
+    let updated = call_function(&a[2], &[current], env)?;
+    table(&a[0])?.borrow_mut().insert(a[1].clone(), updated.clone());
+    Ok(updated)
+}
+
+fn hash_table_update_default(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 4)?;
+    let current = hash_table_ref_default(&[a[0].clone(), a[1].clone(), a[3].clone()], env)?;
+    let updated = call_function(&a[2], &[current], env)?;
+    table(&a[0])?.borrow_mut().insert(a[1].clone(), updated.clone());
+    Ok(updated)
+}
+
+fn hash_table_keys(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 1)?;
+    Ok(LispVal::list(table(&a[0])?.borrow().entries().into_iter().map(|(k, _)| k).collect()))
+}
+
+fn hash_table_values(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 1)?;
+    Ok(LispVal::list(table(&a[0])?.borrow().entries().into_iter().map(|(_, v)| v).collect()))
+}
+
+fn hash_table_to_alist(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 1)?;
+    Ok(LispVal::list(table(&a[0])?.borrow().entries().into_iter().map(|(k, v)| LispVal::cons(k, v)).collect()))
+}
+
+/// Calls `(proc key value)` for every association. The entries are copied first,
+/// so `proc` may safely modify the table.
+fn hash_table_walk(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 2)?;
+    let entries = table(&a[0])?.borrow().entries();
+    for (k, v) in entries {
+        call_function(&a[1], &[k, v], env)?;
+    }
+    Ok(Unspecified)
+}
+
+/// `(hash-table-count table)` returns the number of associations,
+/// `(hash-table-count pred table)` the number of associations satisfying `(pred key value)`.
+fn hash_table_count(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args_between(a, 1, 2)?;
+    if a.len() == 1 {
+        return Ok(Number(table(&a[0])?.borrow().len() as i64));
+    }
+    let entries = table(&a[1])?.borrow().entries();
+    let mut count = 0;
+    for (k, v) in entries {
+        if call_function(&a[0], &[k, v], env)? != Boolean(false) {
+            count += 1;
+        }
+    }
+    Ok(Number(count))
+}
+
+fn hash_table_clear(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 1)?;
+    table(&a[0])?.borrow_mut().entries.clear();
+    Ok(Unspecified)
+}
+
+/// `(hash obj)` exposes the `equal?` hash. Objects that are `equal?` hash alike within a
+/// run, but the values may change between runs and versions.
+fn hash(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 1)?;
+    let mut hasher = DefaultHasher::new();
+    hash_value(&a[0], Equivalence::Equal, &mut hasher);
+    Ok(Number((hasher.finish() >> 1) as i64))
+}
+
+pub fn define_hash_table_functions(e: &mut Env) {
+    e.define_primitive("make-hash-table", make_hash_table).unwrap();
+    e.define_primitive("hash-table?", is_hash_table).unwrap();
+    e.define_primitive("hash-table-ref", hash_table_ref).unwrap();
+    e.define_primitive("hash-table-ref/default", hash_table_ref_default).unwrap();
+    e.define_primitive("hash-table-set!", hash_table_set).unwrap();
+    e.define_primitive("hash-table-delete!", hash_table_delete).unwrap();
+    e.define_primitive("hash-table-contains?", hash_table_contains).unwrap();
+    e.define_primitive("hash-table-exists?", hash_table_contains).unwrap();
+    e.define_primitive("hash-table-update!", hash_table_update).unwrap();
+    e.define_primitive("hash-table-update!/default", hash_table_update_default).unwrap();
+    e.define_primitive("hash-table-keys", hash_table_keys).unwrap();
+    e.define_primitive("hash-table-values", hash_table_values).unwrap();
+    e.define_primitive("hash-table->alist", hash_table_to_alist).unwrap();
+    e.define_primitive("hash-table-walk", hash_table_walk).unwrap();
+    e.define_primitive("hash-table-count", hash_table_count).unwrap();
+    e.define_primitive("hash-table-size", hash_table_count).unwrap();
+    e.define_primitive("hash-table-clear!", hash_table_clear).unwrap();
+    e.define_primitive("hash", hash).unwrap();
+}
+
+#[test]
+fn hash_table_test() {
+    use crate::evaluation::eval_str;
+    let env = &crate::primitive_functions::create_eden_env();
+    eval_str("(define t (make-hash-table equal?))", env).unwrap();
+    eval_str("(hash-table-set! t '(1 2) \"list\")", env).unwrap();
+    eval_str("(hash-table-set! t \"k\" 3)", env).unwrap();
+    assert_eq!(eval_str("(hash-table-ref t (cons 1 '(2)))", env).unwrap().to_string(), "\"list\"");
+    assert_eq!(eval_str("(hash-table-ref t 5 (lambda () 0))", env).unwrap().to_string(), "0");
+    assert!(eval_str("(hash-table-ref t 5)", env).is_err());
+    assert!(eval_str("(make-hash-table string=?)", env).is_err());
+    assert_eq!(eval_str("(hash-table-update! t \"k\" (lambda (v) (+ v 1)))", env).unwrap().to_string(), "4");
+    assert_eq!(eval_str("(hash-table-contains? t \"k\")", env).unwrap().to_string(), "#t");
+    assert_eq!(eval_str("(hash-table-count t)", env).unwrap().to_string(), "2");
+    assert_eq!(eval_str("(hash-table-delete! t \"k\" \"missing\")", env).unwrap().to_string(), "1");
+    assert_eq!(eval_str("(hash-table->alist t)", env).unwrap().to_string(), "(((1 2) . \"list\"))");
+    assert_eq!(eval_str("(= (hash '(1 2)) (hash (cons 1 '(2))))", env).unwrap().to_string(), "#t");
+    eval_str("(define a (list 1 2))", env).unwrap();
+    eval_str("(set-cdr! (cdr a) a)", env).unwrap();
+    eval_str("(define b (list 1 2 1 2))", env).unwrap();
+    eval_str("(set-cdr! (cdr (cdr (cdr b))) b)", env).unwrap();
+    eval_str("(hash-table-set! t a 'cycle)", env).unwrap();
+    assert_eq!(eval_str("(hash-table-ref/default t b #f)", env).unwrap().to_string(), "cycle");
+    assert_eq!(eval_str("(hash-table-ref/default t (list 1 2 1) #f)", env).unwrap().to_string(), "#f");
+}
+
+#[test]
+fn hash_table_walk_test() {
+    use crate::evaluation::eval_str;
+    let env = &crate::primitive_functions::create_eden_env();
+    eval_str("(define t (make-hash-table eqv?))", env).unwrap();
+    eval_str("(hash-table-set! t 1 10)", env).unwrap();
+    eval_str("(hash-table-set! t 2 20)", env).unwrap();
+    eval_str("(define sum 0)", env).unwrap();
+    eval_str("(hash-table-walk t (lambda (k v) (set! sum (+ sum k v))))", env).unwrap();
+    assert_eq!(eval_str("sum", env).unwrap().to_string(), "33");
+    assert_eq!(eval_str("(hash-table-count (lambda (k v) (> k 1)) t)", env).unwrap().to_string(), "1");
+}
@@ -0,0 +1,158 @@
+//! A Lisp interpreter with most of R7RS Scheme.
+//!
+//! [`Interpreter`] holds a global environment to evaluate code in:
+//!
+//! ```
+//! use lisp::{Interpreter, LispVal};
+//!
+//! let lisp = Interpreter::new().unwrap();
+//! lisp.eval_str("(define (square x) (* x x))").unwrap();
+//! assert_eq!(lisp.call("square", &[LispVal::Number(7)]).unwrap(), LispVal::Number(49));
+//! ```
+
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+mod evaluation;
+mod parser;
+mod env;
+mod lispval;
+mod error;
+mod primitive_functions;
+mod hash_table;
+mod list_functions;
+mod sort;
+mod symbol;
+mod port;
+mod filesystem;
+mod printer;
+mod pretty;
+mod format;
+mod record;
+mod promise;
+mod parameter;
+mod library;
+mod prelude;
+
+pub use crate::env::Env;
+pub use crate::error::LispErr;
+pub use crate::hash_table::HashTable;
+pub use crate::lispval::{Arity, LispVal, NativeFn, NativeFunc, Pair, PrimitiveFn};
+pub use crate::parameter::Parameter;
+pub use crate::port::Port;
+pub use crate::promise::Promise;
+pub use crate::record::{Record, RecordType};
+pub use crate::symbol::Symbol;
+
+use crate::error::LispErr::Runtime;
+use crate::evaluation::{call_function, eval};
+use crate::lispval::LispVal::{Eof, Unspecified};
+use crate::port::{current_error, current_input, current_output};
+use crate::prelude::{eval_port, standard_env};
+use crate::pretty::{pretty_string, pretty_width};
+use crate::primitive_functions::create_eden_env;
+
+/// An interpreter and its global environment.
+///
+/// Only the environment belongs to the interpreter. Loaded libraries, provided features,
+/// the library search path, the current ports, the pretty printer width and the symbol
+/// table are kept per thread, so every interpreter on a thread shares them: closing the
+/// current output port or importing a library in one is seen by the others.
+pub struct Interpreter {
+    env: Rc<RefCell<Env>>,
+}
+
+impl Interpreter {
+    /// An interpreter with the built-in procedures and the prelude, which is read from the
+    /// file named by `LISP_PRELUDE` if it is set.
+    pub fn new() -> Result<Interpreter, LispErr> {
+        Ok(Interpreter { env: standard_env()? })
+    }
+
+    /// An interpreter with only the built-in procedures.
+    pub fn without_prelude() -> Interpreter {
+        Interpreter { env: create_eden_env() }
+    }
+
+    /// Puts `dirs` in front of the directories searched by `import` and `require`.
+    pub fn prepend_search_path(dirs: Vec<PathBuf>) {
+        library::prepend_search_path(dirs);
+    }
+
+    /// Evaluates every datum in `source`, returning the value of the last one.
+    pub fn eval_str(&self, source: &str) -> Result<LispVal, LispErr> {
+        eval_port(&Port::string_input(source), &self.env)
+    }
+
+    /// Evaluates the file at `path`, as `load` does.
+    pub fn eval_file(&self, path: impl AsRef<Path>) -> Result<LispVal, LispErr> {
+        library::eval_file(path.as_ref(), &self.env)
+    }
+
+    /// Binds `name` to `value` in the global environment.
+    pub fn define_global(&self, name: &str, value: LispVal) -> Result<LispVal, LispErr> {
+        self.env.borrow_mut().define(name, value)
+    }
+
+    /// Binds `name` to a procedure that calls `f`, which may capture state. Lisp callers get
+    /// an error instead of reaching `f` when the number of arguments does not fit `arity`.
+    pub fn define_native(&self, name: &str, arity: Arity, f: impl Fn(&[LispVal]) -> Result<LispVal, LispErr> + 'static) -> Result<LispVal, LispErr> {
+        let func = NativeFunc::new(name, arity, move |args, _| f(args));
+        self.define_global(name, LispVal::NativeFunc(Rc::new(func)))
+    }
+
+    pub fn get_global(&self, name: &str) -> Result<LispVal, LispErr> {
+        self.env.borrow().get(name)?.ok_or_else(|| Runtime(format!("Variable {} is not defined", name)))
+    }
+
+    /// Calls the procedure bound to `name` with `args`.
+    pub fn call(&self, name: &str, args: &[LispVal]) -> Result<LispVal, LispErr> {
+        call_function(&self.get_global(name)?, args, &self.env)
+    }
+
+    /// Reads data from the current input port and prints their values until end of input
+    /// or `quit`. A datum may span several lines and a line may hold several data. Errors
+    /// from reading or evaluation are reported and the loop goes on; it only stops with an
+    /// error when the ports it talks through fail, for example because the code it ran
+    /// closed them.
+    pub fn repl(&self) -> Result<(), LispErr> {
+        current_output().write_str("Lisp in rust!\n")?;
+        loop {
+            let output = current_output();
+            output.write_str("lisp>>> ")?;
+            output.flush()?;
+            let input = current_input();
+            let datum = match input.read_datum() {
+                Ok(Eof) => return Ok(()),
+                Ok(datum) => datum,
+                Err(e @ LispErr::Runtime(_)) if input.is_open() => {
+                    current_error().write_str(&format!("Error: {}\n", e))?;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            if datum == LispVal::symbol("quit") {
+                return Ok(());
+            }
+            match eval(&datum, &self.env) {
+                Ok(Unspecified) => (),
+                Ok(res) => for v in res.into_values().iter() {
+                    output.write_str(&format!("{}\n", pretty_string(v, pretty_width())))?;
+                },
+                Err(e) => current_error().write_str(&format!("Error: {}\n", e))?,
+            }
+        }
+    }
+}
+
+#[test]
+fn repl_test() {
+    use crate::port::{with_current_input, with_current_output};
+    let lisp = Interpreter::without_prelude();
+    let input = Rc::new(Port::string_input("1 \"a\"\n(+ 1\n 2) ; three\n) 4\n5\nquit\n6\n"));
+    let output = Rc::new(Port::string_output());
+    with_current_input(input, || with_current_output(output.clone(), || lisp.repl())).unwrap();
+    assert_eq!(output.output_string().unwrap(),
+               "Lisp in rust!\nlisp>>> 1\nlisp>>> \"a\"\nlisp>>> 3\nlisp>>> lisp>>> 5\nlisp>>> ");
+}
@@ -0,0 +1,338 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::env::Env;
+use crate::error::LispErr;
+use crate::error::LispErr::Runtime;
+use crate::evaluation::eval;
+use crate::lispval::LispVal;
+use crate::lispval::LispVal::{LispString, Unspecified};
+use crate::parser::parse_vector;
+use crate::port::Port;
+use crate::prelude::standard_env;
+use crate::primitive_functions::{expect_args, expect_args_between};
+use crate::symbol::{symbol, Symbol};
+
+/// A library made by `define-library`. Its definitions live in its own environment, of
+/// which only the exports are visible to importers.
+#[derive(Debug)]
+pub struct Library {
+    env: Rc<RefCell<Env>>,
+    /// Exported names with the names they have inside the library.
+    exports: Vec<(Symbol, Symbol)>,
+}
+
+thread_local! {
+    /// Libraries by the written form of their name, such as `(utils strings)`.
+    static LIBRARIES: RefCell<HashMap<String, Rc<Library>>> = RefCell::new(HashMap::new());
+    /// Libraries whose file is being loaded, to report import cycles.
+    static LOADING: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+    /// Directories searched for `(a b)` as `a/b.sld` or `a/b.scm`.
+    static SEARCH_PATH: RefCell<Vec<PathBuf>> = RefCell::new(default_search_path());
+    /// Features registered by `provide`, after the built-in ones.
+    static FEATURES: RefCell<Vec<LispVal>> = RefCell::new(
+        ["r7rs", "srfi-1", "srfi-8", "srfi-9", "srfi-39", "srfi-41", "srfi-48", "srfi-69"].map(LispVal::symbol).to_vec());
+    /// Directories of the files being loaded, innermost last, which `include` resolves against.
+    static DIRECTORIES: RefCell<Vec<PathBuf>> = const { RefCell::new(vec![]) };
+}
+
+/// The directories in `LISP_PATH` followed by the current directory.
+fn default_search_path() -> Vec<PathBuf> {
+    let mut path: Vec<PathBuf> = std::env::var_os("LISP_PATH").map(|p| std::env::split_paths(&p).collect()).unwrap_or_default();
+    path.push(PathBuf::from("."));
+    path
+}
+
+/// Puts `dirs` at the front of the search path, as the `-I` option does.
+pub fn prepend_search_path(dirs: Vec<PathBuf>) {
+    SEARCH_PATH.with(|p| {
+        p.borrow_mut().splice(0..0, dirs);
+    });
+}
+
+/// `path` relative to the directory of the file being loaded, if any.
+pub fn resolve(path: &str) -> PathBuf {
+    match DIRECTORIES.with(|d| d.borrow().last().cloned()) {
+        Some(dir) => dir.join(path),
+        None => PathBuf::from(path),
+    }
+}
+
+/// Evaluates the forms in the file at `path` in `env`, with `load` and `include` in them
+/// resolving against its directory.
+pub fn eval_file(path: &Path, env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    let name = path.to_string_lossy();
+    let s = Port::open_input_file(&name)?.read_to_end()?;
+    let expressions = match parse_vector(&s) {
+        Ok(("", v)) => v,
+        Ok((rest, _)) => return Err(Runtime(format!("{}: cannot parse {}", name, rest.lines().next().unwrap_or_default()))),
+        Err(err) => return Err(Runtime(format!("{}: {}", name, err))),
+    };
+    let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    DIRECTORIES.with(|d| d.borrow_mut().push(dir));
+    let result = expressions.iter().try_fold(Unspecified, |_, e| eval(e, env));
+    DIRECTORIES.with(|d| d.borrow_mut().pop());
+    result
+}
+
+/// The file defining the library `name` in the first directory of the search path that
+/// has one.
+fn library_file(name: &[LispVal]) -> Result<Option<PathBuf>, LispErr> {
+    let mut relative = PathBuf::new();
+    for part in name {
+        match part {
+            LispVal::Symbol(s) => relative.push(&*s.name()),
+            LispVal::Number(n) => relative.push(n.to_string()),
+            v => return Err(Runtime(format!("Expected symbol or number in library name but got {}", v))),
+        }
+    }
+    let path = SEARCH_PATH.with(|p| p.borrow().iter()
+        .flat_map(|dir| ["sld", "scm"].map(|ext| dir.join(&relative).with_extension(ext)))
+        .find(|path| path.is_file()));
+    Ok(path)
+}
+
+/// The library called `name`, loaded from the search path the first time it is needed.
+fn find_library(name: &LispVal) -> Result<Rc<Library>, LispErr> {
+    let key = name.to_string();
+    if let Some(library) = LIBRARIES.with(|l| l.borrow().get(&key).cloned()) {
+        return Ok(library);
+    }
+    let path = library_file(&name.to_vec()?)?.ok_or_else(|| Runtime(format!("Library {} not found", key)))?;
+    if !LOADING.with(|l| l.borrow_mut().insert(key.clone())) {
+        return Err(Runtime(format!("Library {} imports itself", key)));
+    }
+    let result = standard_env().and_then(|env| eval_file(&path, &env));
+    LOADING.with(|l| l.borrow_mut().remove(&key));
+    result?;
+    LIBRARIES.with(|l| l.borrow().get(&key).cloned())
+        .ok_or_else(|| Runtime(format!("{} does not define library {}", path.display(), key)))
+}
+
+/// The bindings an import set such as `(prefix (only (utils) f g) u:)` makes.
+fn import_set(set: &LispVal) -> Result<Vec<(Symbol, LispVal)>, LispErr> {
+    let items = set.to_vec()?;
+    let head = match items.first() {
+        Some(LispVal::Symbol(s)) => s.name(),
+        _ => return Err(Runtime(format!("Expected import set but got {}", set))),
+    };
+    let names = |v: &[LispVal]| v.iter().map(symbol).collect::<Result<Vec<_>, _>>();
+    match (&*head, items.as_slice()) {
+        ("only", [_, inner, only @ ..]) => {
+            let only = names(only)?;
+            Ok(import_set(inner)?.into_iter().filter(|(n, _)| only.contains(n)).collect())
+        }
+        ("except", [_, inner, except @ ..]) => {
+            let except = names(except)?;
+            Ok(import_set(inner)?.into_iter().filter(|(n, _)| !except.contains(n)).collect())
+        }
+        ("prefix", [_, inner, prefix]) => {
+            let prefix = symbol(prefix)?.name();
+            Ok(import_set(inner)?.into_iter().map(|(n, v)| (Symbol::intern(&format!("{}{}", prefix, n.name())), v)).collect())
+        }
+        ("rename", [_, inner, renames @ ..]) => {
+            let renames = renames.iter().map(|r| names(&r.to_vec()?)).collect::<Result<Vec<_>, _>>()?;
+            let mut bindings = import_set(inner)?;
+            for rename in renames {
+                let [from, to] = rename.as_slice() else {
+                    return Err(Runtime(format!("Expected (name new-name) in {}", set)));
+                };
+                for (n, _) in bindings.iter_mut().filter(|(n, _)| n == from) {
+                    *n = *to;
+                }
+            }
+            Ok(bindings)
+        }
+        // The built-in procedures are visible everywhere, so the standard libraries add nothing.
+        ("scheme" | "srfi", _) => Ok(vec![]),
+        _ => {
+            let library = find_library(set)?;
+            let env = library.env.borrow();
+            library.exports.iter().map(|(external, internal)| {
+                let v = env.get(*internal)?.ok_or_else(|| Runtime(format!("Library {} exports undefined {}", set, internal)))?;
+                Ok((*external, v))
+            }).collect()
+        }
+    }
+}
+
+/// `(import set ...)` defines the exports of each import set in `env`. Values are bound as
+/// they are at the time of the import.
+pub fn import(sets: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    for set in sets {
+        for (name, v) in import_set(set)? {
+            env.borrow_mut().define(name, v)?;
+        }
+    }
+    Ok(Unspecified)
+}
+
+/// `(define-library name declaration ...)` with `export`, `import`, `begin` and `include`
+/// declarations. The body runs in a new environment with only the built-in procedures and
+/// the prelude.
+pub fn define_library(spec: &[LispVal]) -> Result<LispVal, LispErr> {
+    let [name, declarations @ ..] = spec else {
+        return Err(Runtime("define-library: expected library name".to_string()));
+    };
+    let env = standard_env()?;
+    let mut exports = vec![];
+    for declaration in declarations {
+        let items = declaration.to_vec()?;
+        let kind = match items.first() {
+            Some(LispVal::Symbol(s)) => s.name(),
+            _ => return Err(Runtime(format!("Expected library declaration but got {}", declaration))),
+        };
+        match &*kind {
+            "export" => for spec in &items[1..] {
+                match spec {
+                    LispVal::Symbol(s) => exports.push((*s, *s)),
+                    _ => match spec.to_vec()?.as_slice() {
+                        [LispVal::Symbol(r), LispVal::Symbol(internal), LispVal::Symbol(external)] if &*r.name() == "rename" =>
+                            exports.push((*external, *internal)),
+                        _ => return Err(Runtime(format!("Expected export spec but got {}", spec))),
+                    },
+                }
+            },
+            "import" => _ = import(&items[1..], &env)?,
+            "begin" => for form in &items[1..] {
+                eval(form, &env)?;
+            },
+            "include" => for file in &items[1..] {
+                eval_file(&resolve(&file.str()?), &env)?;
+            },
+            _ => return Err(Runtime(format!("Unknown library declaration {}", declaration))),
+        }
+    }
+    LIBRARIES.with(|l| l.borrow_mut().insert(name.to_string(), Rc::new(Library { env, exports })));
+    Ok(Unspecified)
+}
+
+/// `(library-path)` returns the directories searched for libraries; `(library-path list)`
+/// sets them.
+fn library_path(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args_between(a, 0, 1)?;
+    if let Some(dirs) = a.first() {
+        let dirs = dirs.to_vec()?.iter().map(|d| d.str().map(PathBuf::from)).collect::<Result<Vec<_>, _>>()?;
+        SEARCH_PATH.with(|p| *p.borrow_mut() = dirs);
+        return Ok(Unspecified);
+    }
+    let dirs = SEARCH_PATH.with(|p| p.borrow().iter().map(|d| LispString(d.to_string_lossy().into_owned())).collect());
+    Ok(LispVal::list(dirs))
+}
+
+/// `(require name)` loads the file for the feature `name`, a symbol or a list like a
+/// library name, into the top-level environment unless the feature is already provided.
+fn require(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 1)?;
+    let feature = &a[0];
+    if FEATURES.with(|f| f.borrow().contains(feature)) {
+        return Ok(Unspecified);
+    }
+    let parts = match feature {
+        LispVal::Symbol(_) => vec![feature.clone()],
+        _ => feature.to_vec()?,
+    };
+    let path = library_file(&parts)?.ok_or_else(|| Runtime(format!("Cannot find {} in the library path", feature)))?;
+    eval_file(&path, &Env::root(env))?;
+    provide(a, env)
+}
+
+/// `(provide name)` registers the feature `name`, so `require` will not load it again.
+fn provide(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 1)?;
+    FEATURES.with(|f| {
+        let mut features = f.borrow_mut();
+        if !features.contains(&a[0]) {
+            features.push(a[0].clone());
+        }
+    });
+    Ok(Unspecified)
+}
+
+fn features(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 0)?;
+    Ok(LispVal::list(FEATURES.with(|f| f.borrow().clone())))
+}
+
+pub fn define_library_functions(e: &mut Env) {
+    e.define_primitive("library-path", library_path).unwrap();
+    e.define_primitive("require", require).unwrap();
+    e.define_primitive("provide", provide).unwrap();
+    e.define_primitive("features", features).unwrap();
+}
+
+#[test]
+fn library_test() {
+    use crate::evaluation::eval_str;
+    use std::fs;
+    let dir = std::env::temp_dir().join(format!("lisp-library-test-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("shapes")).unwrap();
+    fs::write(dir.join("shapes/square.sld"), "\
+(define-library (shapes square)
+  (export area (rename side-count sides))
+  (import (scheme base) (counter))
+  (begin (define side-count 4))
+  (include \"square-impl.scm\"))").unwrap();
+    fs::write(dir.join("shapes/square-impl.scm"), "(define (area s) (tick) (* s s))").unwrap();
+    fs::write(dir.join("counter.sld"), "\
+(define-library (counter)
+  (export tick count)
+  (begin (define n 0)
+         (define (tick) (set! n (+ n 1)))
+         (define (count) n)))").unwrap();
+    fs::write(dir.join("loop.sld"), "(define-library (loop) (import (loop)))").unwrap();
+
+    let env = &crate::primitive_functions::create_eden_env();
+    let run = |s: &str| eval_str(s, env).unwrap().to_string();
+    env.borrow_mut().define("dir", LispString(dir.to_string_lossy().into_owned())).unwrap();
+    run("(library-path (list dir))");
+    run("(import (shapes square) (prefix (only (counter) count) counter-))");
+    assert_eq!(run("(area 3)"), "9");
+    assert_eq!(run("sides"), "4");
+    assert!(eval_str("side-count", env).is_err());
+    assert!(eval_str("tick", env).is_err());
+    // Both imports share the one instance of (counter).
+    assert_eq!(run("(counter-count)"), "1");
+    run("(import (rename (except (counter) count) (tick bump)))");
+    run("(bump)");
+    assert_eq!(run("(counter-count)"), "2");
+    run("(define-library (local) (export twice) (begin (define (twice x) (* 2 x))))");
+    run("(import (local))");
+    assert_eq!(run("(twice 4)"), "8");
+    assert!(eval_str("(import (missing))", env).is_err());
+    assert!(eval_str("(import (loop))", env).is_err());
+    assert_eq!(run("(library-path)"), format!("({:?})", dir.to_string_lossy()));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn require_test() {
+    use crate::evaluation::eval_str;
+    use std::fs;
+    let dir = std::env::temp_dir().join(format!("lisp-require-test-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("util")).unwrap();
+    fs::write(dir.join("greet.scm"), "(set! loads (+ loads 1)) (define (greet) 'hello)").unwrap();
+    fs::write(dir.join("util/main.scm"), "(load \"helper.scm\") (define main-value (+ helper-value 1))").unwrap();
+    fs::write(dir.join("util/helper.scm"), "(define helper-value 41) (provide 'helper)").unwrap();
+
+    let env = &crate::primitive_functions::create_eden_env();
+    let run = |s: &str| eval_str(s, env).unwrap().to_string();
+    env.borrow_mut().define("dir", LispString(dir.to_string_lossy().into_owned())).unwrap();
+    run("(library-path (list dir))");
+    run("(define loads 0)");
+    run("(require 'greet)");
+    run("((lambda () (require 'greet)))");
+    assert_eq!(run("(list loads (greet))"), "(1 hello)");
+    run("(require '(util main))");
+    assert_eq!(run("main-value"), "42");
+    assert_eq!(run("(memq 'greet (features))"), "(greet helper (util main))");
+    run("(provide 'extra)");
+    run("(require 'extra)");
+    assert!(eval_str("(require 'missing)", env).is_err());
+    assert!(eval_str("(memq 'r7rs (features))", env).unwrap() != LispVal::Boolean(false));
+    fs::remove_dir_all(&dir).unwrap();
+}
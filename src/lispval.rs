@@ -1,69 +1,287 @@
 use std::cell::RefCell;
+use std::collections::HashSet;
 use std::rc::Rc;
 use std::fmt::Display;
 use crate::env::Env;
 use crate::error::LispErr;
 use crate::error::LispErr::Runtime;
+use crate::hash_table::HashTable;
+use crate::parameter::Parameter;
+use crate::port::Port;
+use crate::promise::Promise;
+use crate::record::{Record, RecordType};
+use crate::printer::{print, Mode};
+use crate::symbol::Symbol;
 use crate::lispval::LispVal::Boolean;
 
-#[derive(Clone, Debug, PartialEq)]
+pub type PrimitiveFn = fn(&[LispVal], &Rc<RefCell<Env>>) -> Result<LispVal, LispErr>;
+pub type NativeFn = dyn Fn(&[LispVal], &Rc<RefCell<Env>>) -> Result<LispVal, LispErr>;
+
+/// The numbers of arguments a native procedure takes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Arity {
+    Exactly(usize),
+    AtLeast(usize),
+    Between(usize, usize),
+}
+
+impl Arity {
+    pub fn accepts(&self, n: usize) -> bool {
+        match *self {
+            Arity::Exactly(m) => n == m,
+            Arity::AtLeast(m) => n >= m,
+            Arity::Between(min, max) => (min..=max).contains(&n),
+        }
+    }
+}
+
+impl Display for Arity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Arity::Exactly(n) => write!(f, "{}", n),
+            Arity::AtLeast(n) => write!(f, "at least {}", n),
+            Arity::Between(min, max) => write!(f, "{} to {}", min, max),
+        }
+    }
+}
+
+/// A procedure implemented by a Rust closure, which unlike a `PrimitiveFn` can capture
+/// state. It is only called with a number of arguments its arity accepts.
+pub struct NativeFunc {
+    pub name: Symbol,
+    pub arity: Arity,
+    func: Rc<NativeFn>,
+}
+
+impl NativeFunc {
+    pub fn new(name: &str, arity: Arity, func: impl Fn(&[LispVal], &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> + 'static) -> NativeFunc {
+        NativeFunc { name: Symbol::intern(name), arity, func: Rc::new(func) }
+    }
+
+    pub fn call(&self, args: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+        if !self.arity.accepts(args.len()) {
+            return Err(Runtime(format!("{} expects {} arguments but got {}", self.name, self.arity, args.len())));
+        }
+        (self.func)(args, env)
+    }
+}
+
+impl std::fmt::Debug for NativeFunc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NativeFunc").field("name", &self.name).field("arity", &self.arity).finish_non_exhaustive()
+    }
+}
+
+/// A mutable cons cell. Pairs are shared through `Rc`, so `cdr` is O(1) and
+/// `set-car!`/`set-cdr!` are visible through every reference to the pair.
+#[derive(Debug)]
+pub struct Pair {
+    pub car: RefCell<LispVal>,
+    pub cdr: RefCell<LispVal>,
+}
+
+impl Pair {
+    pub fn car(&self) -> LispVal {
+        self.car.borrow().clone()
+    }
+
+    pub fn cdr(&self) -> LispVal {
+        self.cdr.borrow().clone()
+    }
+}
+
+#[derive(Clone, Debug)]
 pub enum LispVal {
-    Atom(String),
+    Symbol(Symbol),
     Number(i64),
     LispString(String),
+    Char(char),
     Boolean(bool),
-    List(Vec<LispVal>),
-    DottedList(Vec<LispVal>, Box<LispVal>),
+    Nil,
+    Pair(Rc<Pair>),
     Quote(Box<LispVal>),
     Func {
-        args: Vec<String>,
-        vararg: Option<String>,
-        body: Vec<LispVal>,
+        /// Name the procedure was defined under, used when printing it.
+        name: Option<Symbol>,
+        args: Vec<Symbol>,
+        /// `#!optional` parameters with the expressions giving their defaults.
+        optional: Rc<[(Symbol, LispVal)]>,
+        vararg: Option<Symbol>,
+        /// `#!key` parameters, passed as `name: value`, with the expressions giving their defaults.
+        keys: Rc<[(Symbol, LispVal)]>,
+        body: Rc<[LispVal]>,
         closure: Rc<RefCell<Env>>,
     },
-    PrimitiveFunc(fn(&[LispVal], &Rc<RefCell<Env>>) -> Result<LispVal, LispErr>)
+    /// A `case-lambda` procedure, which calls the first `Func` in `clauses` accepting the
+    /// number of arguments it was given.
+    CaseLambda {
+        name: Option<Symbol>,
+        clauses: Vec<LispVal>,
+    },
+    /// A built-in procedure, with the name it was first defined under.
+    PrimitiveFunc {
+        name: Option<Symbol>,
+        func: PrimitiveFn,
+    },
+    NativeFunc(Rc<NativeFunc>),
+    HashTable(Rc<RefCell<HashTable>>),
+    Port(Rc<Port>),
+    /// A first-class environment, as returned by `interaction-environment`.
+    Environment(Rc<RefCell<Env>>),
+    RecordType(Rc<RecordType>),
+    Record(Rc<Record>),
+    /// Result of `(values ...)` with zero or several values; a single value stands for itself.
+    Values(Vec<LispVal>),
+    Promise(Rc<Promise>),
+    Parameter(Rc<Parameter>),
+    Eof,
+    /// Result of procedures that are only called for their side effects.
+    Unspecified,
+}
+
+impl PartialEq for LispVal {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (LispVal::Symbol(a), LispVal::Symbol(b)) => a == b,
+            (LispVal::Number(a), LispVal::Number(b)) => a == b,
+            (LispVal::LispString(a), LispVal::LispString(b)) => a == b,
+            (LispVal::Char(a), LispVal::Char(b)) => a == b,
+            (LispVal::Boolean(a), LispVal::Boolean(b)) => a == b,
+            (LispVal::Nil, LispVal::Nil) => true,
+            (LispVal::Pair(a), LispVal::Pair(b)) => Rc::ptr_eq(a, b),
+            (LispVal::Quote(a), LispVal::Quote(b)) => a == b,
+            (LispVal::Func { args: a1, optional: o1, vararg: v1, keys: k1, body: b1, closure: c1, .. },
+                LispVal::Func { args: a2, optional: o2, vararg: v2, keys: k2, body: b2, closure: c2, .. }) =>
+                a1 == a2 && o1 == o2 && v1 == v2 && k1 == k2 && b1 == b2 && Rc::ptr_eq(c1, c2),
+            (LispVal::CaseLambda { clauses: a, .. }, LispVal::CaseLambda { clauses: b, .. }) => a == b,
+            (LispVal::PrimitiveFunc { func: a, .. }, LispVal::PrimitiveFunc { func: b, .. }) => std::ptr::fn_addr_eq(*a, *b),
+            (LispVal::NativeFunc(a), LispVal::NativeFunc(b)) => Rc::ptr_eq(a, b),
+            (LispVal::HashTable(a), LispVal::HashTable(b)) => Rc::ptr_eq(a, b),
+            (LispVal::Port(a), LispVal::Port(b)) => Rc::ptr_eq(a, b),
+            (LispVal::Environment(a), LispVal::Environment(b)) => Rc::ptr_eq(a, b),
+            (LispVal::RecordType(a), LispVal::RecordType(b)) => Rc::ptr_eq(a, b),
+            (LispVal::Record(a), LispVal::Record(b)) => Rc::ptr_eq(a, b),
+            (LispVal::Values(a), LispVal::Values(b)) => a == b,
+            (LispVal::Promise(a), LispVal::Promise(b)) => Rc::ptr_eq(a, b),
+            (LispVal::Parameter(a), LispVal::Parameter(b)) => Rc::ptr_eq(a, b),
+            (LispVal::Eof, LispVal::Eof) => true,
+            (LispVal::Unspecified, LispVal::Unspecified) => true,
+            _ => false,
+        }
+    }
+}
+
+const CHAR_NAMES: [(char, &str); 9] = [
+    (' ', "space"), ('\n', "newline"), ('\t', "tab"), ('\r', "return"), ('\0', "null"),
+    ('\x07', "alarm"), ('\x08', "backspace"), ('\x7f', "delete"), ('\x1b', "escape"),
+];
+
+/// Name used for `c` in a `#\name` character literal, if it has one.
+pub fn char_name(c: char) -> Option<&'static str> {
+    CHAR_NAMES.iter().find(|(ch, _)| *ch == c).map(|(_, name)| *name)
+}
+
+/// Character written as `#\name`.
+pub fn named_char(name: &str) -> Option<char> {
+    CHAR_NAMES.iter().find(|(_, n)| *n == name).map(|(c, _)| *c)
 }
 
 impl Display for LispVal {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match &self {
-            LispVal::Atom(s) => write!(f, "{}", s),
-            LispVal::Number(n) => write!(f, "{}", n),
-            LispVal::LispString(s) => write!(f, "\"{}\"", s),
-            LispVal::Boolean(b) => write!(f, "{}", b),
-            LispVal::List(v) => {
-                let a: Vec<String> = v.into_iter().map(|i| i.to_string()).collect();
-                write!(f, "({})", a.join(" "))
-            }
-            LispVal::DottedList(v, v1) => {
-                let a: Vec<String> = v.into_iter().map(|i| i.to_string()).collect();
-                write!(f, "({} . {})", a.join(" "), v1.to_string())
-            }
-            LispVal::Quote(q) => write!(f, "quote {}", q),
-            LispVal::Func {args, body, .. } => {
-                let body: Vec<String> = body.into_iter().map(|i| i.to_string()).collect();
-                write!(f, "lambda {} {}", args.join(" "), body.join(" "))
-            },
-            LispVal::PrimitiveFunc(_) => write!(f, "primitiveFunc"),
-        }
+        print(self, Mode::Write, f)
     }
 }
 
 impl LispVal {
+    pub fn symbol(name: &str) -> LispVal {
+        LispVal::Symbol(Symbol::intern(name))
+    }
+
+    /// What `(values v ...)` returns: a single value is passed on as it is.
+    pub fn values(mut values: Vec<LispVal>) -> LispVal {
+        if values.len() == 1 {
+            values.pop().unwrap()
+        } else {
+            LispVal::Values(values)
+        }
+    }
+
+    /// The values `self` stands for, as received by a `call-with-values` consumer.
+    pub fn into_values(self) -> Vec<LispVal> {
+        match self {
+            LispVal::Values(values) => values,
+            v => vec![v],
+        }
+    }
+
+    /// Gives an anonymous procedure the name it is being defined under.
+    pub fn named(mut self, name: Symbol) -> LispVal {
+        if let LispVal::Func { name: n @ None, .. } | LispVal::CaseLambda { name: n @ None, .. }
+            | LispVal::PrimitiveFunc { name: n @ None, .. } = &mut self {
+            *n = Some(name);
+        }
+        self
+    }
+
+    pub fn cons(car: LispVal, cdr: LispVal) -> LispVal {
+        LispVal::Pair(Rc::new(Pair { car: RefCell::new(car), cdr: RefCell::new(cdr) }))
+    }
+
+    pub fn list(items: Vec<LispVal>) -> LispVal {
+        LispVal::dotted_list(items, LispVal::Nil)
+    }
+
+    pub fn dotted_list(items: Vec<LispVal>, tail: LispVal) -> LispVal {
+        items.into_iter().rev().fold(tail, |cdr, car| LispVal::cons(car, cdr))
+    }
+
+    /// Splits a chain of pairs into its elements and the final cdr, which is `Nil` for a
+    /// proper list. Fails instead of looping forever on a circular list.
+    pub fn split_improper(&self) -> Result<(Vec<LispVal>, LispVal), LispErr> {
+        let mut items = vec![];
+        let mut tail = self.clone();
+        let mut slow = self.clone();
+        while let LispVal::Pair(p) = tail {
+            items.push(p.car());
+            tail = p.cdr();
+            if items.len() % 2 == 0 {
+                if let LispVal::Pair(s) = slow {
+                    slow = s.cdr();
+                }
+                if let (LispVal::Pair(a), LispVal::Pair(b)) = (&slow, &tail) {
+                    if Rc::ptr_eq(a, b) {
+                        return Err(Runtime("Expected list but got a circular list".to_string()));
+                    }
+                }
+            }
+        }
+        Ok((items, tail))
+    }
+
+    /// Elements of a proper list.
+    pub fn to_vec(&self) -> Result<Vec<LispVal>, LispErr> {
+        match self.split_improper()? {
+            (items, LispVal::Nil) => Ok(items),
+            _ => Err(Runtime(format!("Expected proper list but got {}", self))),
+        }
+    }
+
     pub fn num(&self) -> Result<i64, LispErr> {
         match self {
-            LispVal::Number(n) => Ok(n.clone()),
-            LispVal::LispString(s) => Ok(s.parse().unwrap()),
-            _ => return Err(Runtime(format!("Left operand must be an integer {:?}", self))),
+            LispVal::Number(n) => Ok(*n),
+            LispVal::LispString(s) => s.parse()
+                .map_err(|_| Runtime(format!("Left operand must be an integer {:?}", self))),
+            _ => Err(Runtime(format!("Left operand must be an integer {:?}", self))),
         }
     }
 
     pub fn bool(&self) -> Result<bool, LispErr> {
         match self {
-            Boolean(b) => Ok(b.clone()),
-            LispVal::Number(n) => Ok(n.clone() != 0),
-            LispVal::LispString(s) => Ok(s.parse().unwrap()),
-            _ => return Err(Runtime(format!("Left operand must be an integer {:?}", self))),
+            Boolean(b) => Ok(*b),
+            LispVal::Number(n) => Ok(*n != 0),
+            LispVal::LispString(s) => s.parse()
+                .map_err(|_| Runtime(format!("Left operand must be a boolean {:?}", self))),
+            _ => Err(Runtime(format!("Left operand must be an integer {:?}", self))),
         }
     }
 
@@ -71,7 +289,63 @@ impl LispVal {
         match self {
             LispVal::Number(n) => Ok(n.to_string()),
             LispVal::LispString(s) => Ok(s.clone()),
-            _ => return Err(Runtime(format!("Left operand must be a string {:?}", self))),
+            _ => Err(Runtime(format!("Left operand must be a string {:?}", self))),
+        }
+    }
+}
+
+/// `eqv?`: symbols, numbers, strings and booleans compare by value, values with
+/// identity (pairs, hash tables) compare by reference.
+pub fn eqv(a: &LispVal, b: &LispVal) -> bool {
+    a == b
+}
+
+/// `equal?`: like `eqv?`, but pairs are compared recursively by their contents. This
+/// terminates on circular structure, where two lists are equal if unfolding them forever
+/// never finds a difference.
+pub fn equal(a: &LispVal, b: &LispVal) -> bool {
+    equal_in(a, b, &mut Visited::default())
+}
+
+/// Pairs compared before `equal?` starts tracking them. Most comparisons finish sooner and
+/// never allocate; a circular one keeps going and is caught once tracking starts.
+const UNTRACKED_PAIRS: usize = 1000;
+
+/// The pairs of pairs `equal?` has compared, which can be assumed equal when met again:
+/// any difference will be found along the first path that reached them.
+#[derive(Default)]
+struct Visited {
+    count: usize,
+    seen: Option<HashSet<(*const Pair, *const Pair)>>,
+}
+
+impl Visited {
+    /// Whether `x` and `y` still need comparing.
+    fn first_visit(&mut self, x: &Rc<Pair>, y: &Rc<Pair>) -> bool {
+        self.count += 1;
+        if self.count <= UNTRACKED_PAIRS {
+            return true;
         }
+        self.seen.get_or_insert_with(HashSet::new).insert((Rc::as_ptr(x), Rc::as_ptr(y)))
     }
-}
\ No newline at end of file
+}
+
+fn equal_in(a: &LispVal, b: &LispVal, visited: &mut Visited) -> bool {
+    let (mut a, mut b) = (a.clone(), b.clone());
+    loop {
+        match (&a, &b) {
+            (LispVal::Pair(x), LispVal::Pair(y)) => {
+                if Rc::ptr_eq(x, y) || !visited.first_visit(x, y) {
+                    return true;
+                }
+                if !equal_in(&x.car.borrow(), &y.car.borrow(), visited) {
+                    return false;
+                }
+                let (next_a, next_b) = (x.cdr(), y.cdr());
+                (a, b) = (next_a, next_b);
+            }
+            (LispVal::Quote(x), LispVal::Quote(y)) => return equal_in(x, y, visited),
+            _ => return eqv(&a, &b),
+        }
+    }
+}
@@ -0,0 +1,364 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::env::Env;
+use crate::error::LispErr;
+use crate::error::LispErr::Runtime;
+use crate::evaluation::call_function;
+use crate::lispval::{equal, eqv, LispVal};
+use crate::lispval::LispVal::{Boolean, Nil, Number, Unspecified};
+use crate::primitive_functions::{expect_args, expect_args_between};
+
+pub fn is_true(v: &LispVal) -> bool {
+    !matches!(v, Boolean(false))
+}
+
+fn at_least(a: &[LispVal], n: usize) -> Result<(), LispErr> {
+    if a.len() < n {
+        return Err(Runtime(format!("Expected at least {} arguments but got {}", n, a.len())));
+    }
+    Ok(())
+}
+
+fn index(v: &LispVal) -> Result<usize, LispErr> {
+    match v.num()? {
+        n if n >= 0 => Ok(n as usize),
+        n => Err(Runtime(format!("Expected non-negative index but got {}", n))),
+    }
+}
+
+/// Elements of every list argument, truncated to the shortest one, as rows of arguments
+/// for the procedure of an n-ary `map`, `for-each`, `fold`, `any` or `every`.
+fn zip_lists(lists: &[LispVal]) -> Result<Vec<Vec<LispVal>>, LispErr> {
+    let lists = lists.iter().map(|l| l.to_vec()).collect::<Result<Vec<_>, _>>()?;
+    let len = lists.iter().map(|l| l.len()).min().unwrap_or(0);
+    Ok((0..len).map(|i| lists.iter().map(|l| l[i].clone()).collect()).collect())
+}
+
+/// Compares with the optional user supplied equality procedure, `default` otherwise.
+fn same(eq: Option<&LispVal>, default: fn(&LispVal, &LispVal) -> bool,
+        x: &LispVal, y: &LispVal, env: &Rc<RefCell<Env>>) -> Result<bool, LispErr> {
+    match eq {
+        Some(f) => Ok(is_true(&call_function(f, &[x.clone(), y.clone()], env)?)),
+        None => Ok(default(x, y)),
+    }
+}
+
+fn list(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    Ok(LispVal::list(a.to_vec()))
+}
+
+fn is_list(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 1)?;
+    Ok(Boolean(a[0].to_vec().is_ok()))
+}
+
+fn length(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 1)?;
+    Ok(Number(a[0].to_vec()?.len() as i64))
+}
+
+/// `(append list ... obj)`: every argument but the last is copied, the last one is shared.
+fn append(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    let Some((last, init)) = a.split_last() else {
+        return Ok(Nil);
+    };
+    let mut items = vec![];
+    for l in init {
+        items.extend(l.to_vec()?);
+    }
+    Ok(LispVal::dotted_list(items, last.clone()))
+}
+
+fn reverse(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 1)?;
+    Ok(a[0].to_vec()?.into_iter().fold(Nil, |acc, x| LispVal::cons(x, acc)))
+}
+
+fn tail(l: &LispVal, k: usize) -> Result<LispVal, LispErr> {
+    let mut l = l.clone();
+    for _ in 0..k {
+        l = match l {
+            LispVal::Pair(p) => p.cdr(),
+            _ => return Err(Runtime(format!("Index {} is out of range", k))),
+        };
+    }
+    Ok(l)
+}
+
+fn list_tail(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 2)?;
+    tail(&a[0], index(&a[1])?)
+}
+
+fn list_ref(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 2)?;
+    match tail(&a[0], index(&a[1])?)? {
+        LispVal::Pair(p) => Ok(p.car()),
+        _ => Err(Runtime(format!("Index {} is out of range", a[1]))),
+    }
+}
+
+fn last(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 1)?;
+    match a[0].to_vec()?.pop() {
+        Some(v) => Ok(v),
+        None => Err(Runtime("Expected non-empty list".to_string())),
+    }
+}
+
+fn take(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 2)?;
+    let k = index(&a[1])?;
+    let (items, _) = a[0].split_improper()?;
+    if items.len() < k {
+        return Err(Runtime(format!("Index {} is out of range", k)));
+    }
+    Ok(LispVal::list(items[..k].to_vec()))
+}
+
+fn drop(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 2)?;
+    tail(&a[0], index(&a[1])?)
+}
+
+/// `(iota count [start [step]])`
+fn iota(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args_between(a, 1, 3)?;
+    let count = index(&a[0])?;
+    let start = a.get(1).map(|v| v.num()).transpose()?.unwrap_or(0);
+    let step = a.get(2).map(|v| v.num()).transpose()?.unwrap_or(1);
+    let items = (0..count as i64)
+        .map(|i| i.checked_mul(step).and_then(|d| start.checked_add(d)).map(Number))
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| Runtime("Integer overflow".to_string()))?;
+    Ok(LispVal::list(items))
+}
+
+fn map(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    at_least(a, 2)?;
+    let results = zip_lists(&a[1..])?.into_iter()
+        .map(|args| call_function(&a[0], &args, env))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(LispVal::list(results))
+}
+
+fn for_each(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    at_least(a, 2)?;
+    for args in zip_lists(&a[1..])? {
+        call_function(&a[0], &args, env)?;
+    }
+    Ok(Unspecified)
+}
+
+fn append_map(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    at_least(a, 2)?;
+    let mut items = vec![];
+    for args in zip_lists(&a[1..])? {
+        items.extend(call_function(&a[0], &args, env)?.to_vec()?);
+    }
+    Ok(LispVal::list(items))
+}
+
+fn split_by(pred: &LispVal, l: &LispVal, env: &Rc<RefCell<Env>>) -> Result<(Vec<LispVal>, Vec<LispVal>), LispErr> {
+    let mut selected = vec![];
+    let mut rejected = vec![];
+    for x in l.to_vec()? {
+        if is_true(&call_function(pred, std::slice::from_ref(&x), env)?) {
+            selected.push(x);
+        } else {
+            rejected.push(x);
+        }
+    }
+    Ok((selected, rejected))
+}
+
+fn filter(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 2)?;
+    Ok(LispVal::list(split_by(&a[0], &a[1], env)?.0))
+}
+
+fn remove(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 2)?;
+    Ok(LispVal::list(split_by(&a[0], &a[1], env)?.1))
+}
+
+/// `(partition pred list)` returns the list of matching and the list of other elements.
+fn partition(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 2)?;
+    let (selected, rejected) = split_by(&a[0], &a[1], env)?;
+    Ok(LispVal::values(vec![LispVal::list(selected), LispVal::list(rejected)]))
+}
+
+/// `(fold kons knil list ...)` calls `(kons elem ... acc)` from left to right.
+fn fold(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    at_least(a, 3)?;
+    zip_lists(&a[2..])?.into_iter().try_fold(a[1].clone(), |acc, mut args| {
+        args.push(acc);
+        call_function(&a[0], &args, env)
+    })
+}
+
+/// `(fold-right kons knil list ...)` calls `(kons elem ... acc)` from right to left.
+fn fold_right(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    at_least(a, 3)?;
+    zip_lists(&a[2..])?.into_iter().rev().try_fold(a[1].clone(), |acc, mut args| {
+        args.push(acc);
+        call_function(&a[0], &args, env)
+    })
+}
+
+/// `(reduce f ridentity list)` is `fold` seeded with the first element, or `ridentity`
+/// for an empty list.
+fn reduce(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 3)?;
+    let items = a[2].to_vec()?;
+    let Some((first, rest)) = items.split_first() else {
+        return Ok(a[1].clone());
+    };
+    rest.iter().try_fold(first.clone(), |acc, x| call_function(&a[0], &[x.clone(), acc], env))
+}
+
+fn any(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    at_least(a, 2)?;
+    for args in zip_lists(&a[1..])? {
+        let r = call_function(&a[0], &args, env)?;
+        if is_true(&r) {
+            return Ok(r);
+        }
+    }
+    Ok(Boolean(false))
+}
+
+fn every(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    at_least(a, 2)?;
+    let mut last = Boolean(true);
+    for args in zip_lists(&a[1..])? {
+        last = call_function(&a[0], &args, env)?;
+        if !is_true(&last) {
+            return Ok(last);
+        }
+    }
+    Ok(last)
+}
+
+/// Shared by `member`, `memq` and `memv`: the first tail of the list whose car matches.
+fn find_tail(a: &[LispVal], default: fn(&LispVal, &LispVal) -> bool, env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args_between(a, 2, 3)?;
+    a[1].to_vec()?;
+    let mut l = a[1].clone();
+    while let LispVal::Pair(p) = l {
+        if same(a.get(2), default, &a[0], &p.car(), env)? {
+            return Ok(LispVal::Pair(p));
+        }
+        l = p.cdr();
+    }
+    Ok(Boolean(false))
+}
+
+/// Shared by `assoc`, `assq` and `assv`: the first pair of the alist whose car matches.
+fn find_assoc(a: &[LispVal], default: fn(&LispVal, &LispVal) -> bool, env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args_between(a, 2, 3)?;
+    for entry in a[1].to_vec()? {
+        let LispVal::Pair(p) = &entry else {
+            return Err(Runtime(format!("Expected association list entry but got {}", entry)));
+        };
+        if same(a.get(2), default, &a[0], &p.car(), env)? {
+            return Ok(entry);
+        }
+    }
+    Ok(Boolean(false))
+}
+
+fn delete(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args_between(a, 2, 3)?;
+    let mut kept = vec![];
+    for x in a[1].to_vec()? {
+        if !same(a.get(2), equal, &a[0], &x, env)? {
+            kept.push(x);
+        }
+    }
+    Ok(LispVal::list(kept))
+}
+
+fn delete_duplicates(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args_between(a, 1, 2)?;
+    let mut kept: Vec<LispVal> = vec![];
+    'outer: for x in a[0].to_vec()? {
+        for k in &kept {
+            if same(a.get(1), equal, k, &x, env)? {
+                continue 'outer;
+            }
+        }
+        kept.push(x);
+    }
+    Ok(LispVal::list(kept))
+}
+
+pub fn define_list_functions(e: &mut Env) {
+    e.define_primitive("list", list).unwrap();
+    e.define_primitive("list?", is_list).unwrap();
+    e.define_primitive("length", length).unwrap();
+    e.define_primitive("append", append).unwrap();
+    e.define_primitive("reverse", reverse).unwrap();
+    e.define_primitive("list-ref", list_ref).unwrap();
+    e.define_primitive("list-tail", list_tail).unwrap();
+    e.define_primitive("last", last).unwrap();
+    e.define_primitive("take", take).unwrap();
+    e.define_primitive("drop", drop).unwrap();
+    e.define_primitive("iota", iota).unwrap();
+    e.define_primitive("map", map).unwrap();
+    e.define_primitive("for-each", for_each).unwrap();
+    e.define_primitive("append-map", append_map).unwrap();
+    e.define_primitive("filter", filter).unwrap();
+    e.define_primitive("remove", remove).unwrap();
+    e.define_primitive("partition", partition).unwrap();
+    e.define_primitive("fold", fold).unwrap();
+    e.define_primitive("fold-right", fold_right).unwrap();
+    e.define_primitive("reduce", reduce).unwrap();
+    e.define_primitive("any", any).unwrap();
+    e.define_primitive("every", every).unwrap();
+    e.define_primitive("member", |a, env| find_tail(a, equal, env)).unwrap();
+    e.define_primitive("memq", |a, env| find_tail(a, eqv, env)).unwrap();
+    e.define_primitive("memv", |a, env| find_tail(a, eqv, env)).unwrap();
+    e.define_primitive("assoc", |a, env| find_assoc(a, equal, env)).unwrap();
+    e.define_primitive("assq", |a, env| find_assoc(a, eqv, env)).unwrap();
+    e.define_primitive("assv", |a, env| find_assoc(a, eqv, env)).unwrap();
+    e.define_primitive("delete", delete).unwrap();
+    e.define_primitive("delete-duplicates", delete_duplicates).unwrap();
+}
+
+#[test]
+fn list_functions_test() {
+    use crate::evaluation::eval_str;
+    let env = &crate::primitive_functions::create_eden_env();
+    let run = |s: &str| eval_str(s, env).unwrap().to_string();
+    assert_eq!(run("(append '(1 2) '() '(3) 4)"), "(1 2 3 . 4)");
+    assert_eq!(run("(reverse (iota 4 1))"), "(4 3 2 1)");
+    assert_eq!(run("(list-ref '(a b c) 2)"), "c");
+    assert_eq!(run("(map + '(1 2 3) '(10 20))"), "(11 22)");
+    assert_eq!(run("(fold cons '() '(1 2 3))"), "(3 2 1)");
+    assert_eq!(run("(fold-right cons '() '(1 2 3))"), "(1 2 3)");
+    assert_eq!(run("(reduce + 0 '(1 2 3 4))"), "10");
+    assert_eq!(run("(filter (lambda (x) (> x 1)) '(1 2 3))"), "(2 3)");
+    assert_eq!(run("(call-with-values (lambda () (partition (lambda (x) (> x 1)) '(1 2 3))) list)"), "((2 3) (1))");
+    assert_eq!(run("(assoc '(b) '((a . 1) ((b) . 2)))"), "((b) . 2)");
+    assert_eq!(run("(member 2 '(1 2 3))"), "(2 3)");
+    assert_eq!(run("(delete-duplicates '(1 2 1 3 2))"), "(1 2 3)");
+    assert_eq!(run("(every (lambda (x) x) '(1 2))"), "2");
+    assert_eq!(run("(append-map (lambda (x) (list x x)) '(1 2))"), "(1 1 2 2)");
+    assert_eq!(run("(take '(1 2 3) 2)"), "(1 2)");
+}
+
+#[test]
+fn list_functions_error_test() {
+    use crate::evaluation::eval_str;
+    let env = &crate::primitive_functions::create_eden_env();
+    assert!(eval_str("(length '(1 2 . 3))", env).is_err());
+    assert!(eval_str("(map car '(1 . 2))", env).is_err());
+    assert!(eval_str("(list-ref '(1 2) 5)", env).is_err());
+    assert!(eval_str("(assq 'a '(1 2))", env).is_err());
+    assert!(eval_str("(last '())", env).is_err());
+    assert!(eval_str("(iota 3 9223372036854775807 9223372036854775807)", env).is_err());
+    assert!(eval_str("(iota 2 -9223372036854775808 -1)", env).is_err());
+}
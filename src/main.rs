@@ -1,75 +1,39 @@
-use std::io::{stdin, Write};
+use std::path::PathBuf;
 
-use evaluation::eval;
-use parser::parse_expr;
-use primitive_functions::load;
-
-use crate::primitive_functions::create_eden_env;
-use crate::lispval::LispVal::LispString;
-
-mod evaluation;
-mod parser;
-mod env;
-mod lispval;
-mod error;
-mod primitive_functions;
+use lisp::Interpreter;
 
 fn main() {
-    println!("Lisp in rust!");
-    let env = create_eden_env();
-    load(&[LispString("/Users/izraigo/Projects/lisp/src/stdLib.scm".to_string())], &env).expect("Error");
-
-    loop {
-        let mut s = String::new();
-        print!("lisp>>> ");
-        std::io::stdout().flush().unwrap();
-        stdin().read_line(&mut s).expect("Enter expression");
-        if s.trim_end() == "quit" {
-            break
-        }
-        match parse_expr(&s) {
-            Ok((_, lisp_val)) => match eval(&lisp_val, &env) {
-                Ok(res) => println!("{}", res),
-                Err(e) => println!("Error: {}", e),
+    let usage = "Usage: lisp [--no-prelude] [-I directory]...";
+    let mut prelude = true;
+    let mut include = vec![];
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--no-prelude" => prelude = false,
+            "-I" => match args.next() {
+                Some(dir) => include.push(PathBuf::from(dir)),
+                None => {
+                    eprintln!("-I needs a directory\n{}", usage);
+                    std::process::exit(2);
+                }
             },
-            Err(e) => println!("Error: {}", e),
+            _ if arg.starts_with("-I") => include.push(PathBuf::from(&arg[2..])),
+            _ => {
+                eprintln!("Unknown option {}\n{}", arg, usage);
+                std::process::exit(2);
+            }
         }
     }
-}
-
-#[test]
-fn eval_test() {
-    let env = &create_eden_env();
-    let (_, e) = parse_expr("(+ 2 \"3\")").unwrap();
-    println!("Expression input: {}", e);
-    let res = eval(&e, env).unwrap();
-    println!("Expression input: {}", res);
-    assert_eq!(res.to_string(), "5");
-
-    let (_, e) = parse_expr("(- (+ 4 6 3) 3 5 2)").unwrap();
-    println!("Expression input: {}", e);
-    let res = eval(&e, env).unwrap();
-    println!("Expression input: {}", res);
-    assert_eq!(res.to_string(), "3");
-
-    let (_, e) = parse_expr("(+ 2 (- 4 1))").unwrap();
-    println!("Expression input: {}", e);
-    let res = eval(&e, env).unwrap();
-    println!("Expression input: {}", res);
-    assert_eq!(res.to_string(), "5");
-}
-#[test]
-fn eval_test2() {
-    let env = &create_eden_env();
-    let (_, e) = parse_expr("(define a 2)").unwrap();
-    println!("Expression input: {}", e);
-    let res = eval(&e, env).unwrap();
-    println!("Expression result: {}", res);
-    assert_eq!(res.to_string(), "2");
-
-    let (_, e) = parse_expr("a").unwrap();
-    println!("Expression input: {}", e);
-    let res = eval(&e, env).unwrap();
-    println!("Expression result: {}", res);
-    assert_eq!(res.to_string(), "2");
+    Interpreter::prepend_search_path(include);
+    let interpreter = match prelude {
+        true => Interpreter::new().unwrap_or_else(|e| {
+            eprintln!("Error loading prelude: {}", e);
+            Interpreter::without_prelude()
+        }),
+        false => Interpreter::without_prelude(),
+    };
+    if let Err(e) = interpreter.repl() {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
 }
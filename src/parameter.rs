@@ -0,0 +1,105 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::env::Env;
+use crate::error::LispErr;
+use crate::error::LispErr::WrongType;
+use crate::evaluation::call_function;
+use crate::lispval::LispVal;
+use crate::primitive_functions::expect_args_between;
+
+/// A parameter object made by `make-parameter`. Calling it returns its current value,
+/// which `parameterize` rebinds for the dynamic extent of its body.
+#[derive(Debug)]
+pub struct Parameter {
+    value: RefCell<LispVal>,
+    /// Procedure applied to the initial value and to every value given to `parameterize`.
+    converter: Option<LispVal>,
+}
+
+impl Parameter {
+    /// A parameter holding `value`, which is expected to be converted already.
+    pub fn new(value: LispVal, converter: Option<LispVal>) -> Rc<Parameter> {
+        Rc::new(Parameter { value: RefCell::new(value), converter })
+    }
+
+    pub fn get(&self) -> LispVal {
+        self.value.borrow().clone()
+    }
+
+    pub fn convert(&self, v: LispVal, env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+        match &self.converter {
+            Some(f) => call_function(f, &[v], env),
+            None => Ok(v),
+        }
+    }
+}
+
+pub fn parameter(v: &LispVal) -> Result<Rc<Parameter>, LispErr> {
+    match v {
+        LispVal::Parameter(p) => Ok(p.clone()),
+        _ => Err(WrongType("parameter".to_string(), v.clone())),
+    }
+}
+
+/// Puts the saved values back when dropped, so they are restored however the body is
+/// left: normally, through an error or by unwinding.
+struct Restore(Vec<(Rc<Parameter>, LispVal)>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        for (p, v) in self.0.drain(..).rev() {
+            p.value.replace(v);
+        }
+    }
+}
+
+/// Binds each parameter to its value, which is expected to be converted already, while
+/// `f` runs.
+pub fn with_parameters<T>(bindings: Vec<(Rc<Parameter>, LispVal)>, f: impl FnOnce() -> T) -> T {
+    let saved = bindings.into_iter().map(|(p, v)| {
+        let previous = p.value.replace(v);
+        (p, previous)
+    }).collect();
+    let _restore = Restore(saved);
+    f()
+}
+
+/// `(make-parameter value [converter])`
+fn make_parameter(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args_between(a, 1, 2)?;
+    let converter = a.get(1).cloned();
+    let value = match &converter {
+        Some(f) => call_function(f, &a[..1], env)?,
+        None => a[0].clone(),
+    };
+    Ok(LispVal::Parameter(Parameter::new(value, converter)))
+}
+
+pub fn define_parameter_functions(e: &mut Env) {
+    e.define_primitive("make-parameter", make_parameter).unwrap();
+}
+
+#[test]
+fn parameter_test() {
+    use crate::evaluation::eval_str;
+    let env = &crate::primitive_functions::create_eden_env();
+    let run = |s: &str| eval_str(s, env).unwrap().to_string();
+    run("(define radix (make-parameter 10))");
+    run("(define (show) (radix))");
+    assert_eq!(run("(show)"), "10");
+    assert_eq!(run("(parameterize ((radix 2)) (show))"), "2");
+    assert_eq!(run("(parameterize ((radix 2)) (parameterize ((radix 8)) (show)))"), "8");
+    assert_eq!(run("(show)"), "10");
+    run("(define doubled (make-parameter 1 (lambda (x) (* x 2))))");
+    assert_eq!(run("(doubled)"), "2");
+    assert_eq!(run("(parameterize ((doubled 5) (radix (doubled))) (list (doubled) (radix)))"), "(10 2)");
+    assert!(eval_str("(parameterize ((radix 16)) (car '()))", env).is_err());
+    assert_eq!(run("(radix)"), "10");
+    assert!(eval_str("(parameterize ((show 1)) 1)", env).is_err());
+    assert_eq!(run("radix"), "#<parameter>");
+    run("(define p (open-output-string))");
+    assert_eq!(run("(with-output-to-string (lambda () (display 1) (parameterize ((current-output-port p)) (display 2)) (display 3)))"), "\"13\"");
+    assert_eq!(run("(get-output-string p)"), "\"2\"");
+    assert!(eval_str("(parameterize ((current-output-port 5)) 1)", env).is_err());
+}
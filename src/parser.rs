@@ -1,26 +1,94 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
 use nom::branch::alt;
-use nom::bytes::complete::{is_a, is_not};
-use nom::character::complete::{alpha1, alphanumeric1, char, digit1, multispace1, space0};
+use nom::bytes::complete::{is_a, tag};
+use nom::character::complete::{alpha1, alphanumeric1, char, digit1, one_of};
+use nom::combinator::{opt, recognize};
+use nom::error::{Error, ErrorKind};
 use nom::IResult;
-use nom::multi::{many0, many1, separated_list0};
-use nom::sequence::{delimited, separated_pair};
+use nom::multi::many0;
+use nom::sequence::{pair, preceded, separated_pair};
+
+use crate::lispval::{named_char, LispVal, Pair};
+use crate::symbol::Symbol;
+
+fn error<T>(input: &str, kind: ErrorKind) -> IResult<&str, T> {
+    Err(nom::Err::Error(Error::new(input, kind)))
+}
 
-use crate::lispval::LispVal;
-use crate::lispval::LispVal::List;
+/// The character after a backslash in a string or `|symbol|`: `\n`, `\t`, `\r`, `\a`,
+/// `\b`, `\x41;` or the character itself.
+fn parse_escape(input: &str) -> IResult<&str, char> {
+    let mut chars = input.chars();
+    let c = match chars.next() {
+        Some('n') => '\n',
+        Some('t') => '\t',
+        Some('r') => '\r',
+        Some('a') => '\x07',
+        Some('b') => '\x08',
+        Some('x') => {
+            let rest = chars.as_str();
+            let Some(end) = rest.find(';') else { return error(input, ErrorKind::Escaped) };
+            match u32::from_str_radix(&rest[..end], 16).ok().and_then(char::from_u32) {
+                Some(c) => return Ok((&rest[end + 1..], c)),
+                None => return error(input, ErrorKind::Escaped),
+            }
+        }
+        Some(c) => c,
+        None => return error(input, ErrorKind::Escaped),
+    };
+    Ok((chars.as_str(), c))
+}
+
+/// Text between two `delimiter`s, with backslash escapes.
+fn parse_delimited(delimiter: char, input: &str) -> IResult<&str, String> {
+    let (mut input, _) = char(delimiter)(input)?;
+    let mut s = String::new();
+    loop {
+        let mut chars = input.chars();
+        match chars.next() {
+            Some(c) if c == delimiter => return Ok((chars.as_str(), s)),
+            Some('\\') => {
+                let (rest, c) = parse_escape(chars.as_str())?;
+                s.push(c);
+                input = rest;
+            }
+            Some(c) => {
+                s.push(c);
+                input = chars.as_str();
+            }
+            None => return error(input, ErrorKind::Char),
+        }
+    }
+}
 
 fn parse_string(input: &str) -> IResult<&str, LispVal> {
-    delimited(char('"'), is_not("\""), char('"'))(input)
-        .map(|(i, o)| (i, LispVal::LispString(String::from(o))))
+    parse_delimited('"', input).map(|(i, s)| (i, LispVal::LispString(s)))
+}
+
+/// `|two words|`: a symbol whose name would not read as an atom.
+fn parse_bar_symbol(input: &str) -> IResult<&str, LispVal> {
+    parse_delimited('|', input).map(|(i, s)| (i, LispVal::symbol(&s)))
 }
 
 #[test]
 fn string_parser_test() {
     let output = parse_string("\"hello\"").unwrap();
     assert_eq!(output, ("", LispVal::LispString("hello".to_owned())));
+    assert_eq!(parse_string("\"\"").unwrap(), ("", LispVal::LispString(String::new())));
+    assert_eq!(parse_string(r#""a\"b\\c\nd\x3bb;""#).unwrap(), ("", LispVal::LispString("a\"b\\c\nd\u{3bb}".to_owned())));
+    assert!(parse_string("\"open").is_err());
+    assert_eq!(parse_bar_symbol("|two words|").unwrap(), ("", LispVal::symbol("two words")));
 }
 
 fn parse_number(input: &str) -> IResult<&str, LispVal> {
-    many1(digit1)(input).map(|(i, o)| (i, LispVal::Number(o.join("").parse::<i64>().unwrap())))
+    let (rest, digits) = recognize(pair(opt(one_of("+-")), digit1))(input)?;
+    match digits.parse::<i64>() {
+        Ok(n) => Ok((rest, LispVal::Number(n))),
+        Err(_) => error(input, ErrorKind::Digit),
+    }
 }
 
 #[test]
@@ -28,13 +96,16 @@ fn number_parser_test() {
     assert!(parse_number("j5").is_err());
     assert!(parse_number("jlsdf").is_err());
     assert_eq!(parse_number("23").unwrap(), ("", LispVal::Number(23)));
+    assert_eq!(parse_number("-23)").unwrap(), (")", LispVal::Number(-23)));
+    assert!(parse_number("-").is_err());
+    assert!(parse_number("99999999999999999999").is_err());
 }
 
 fn match_symbols(input: String) -> LispVal {
     match input.as_str() {
         "#t" => LispVal::Boolean(true),
         "#f" => LispVal::Boolean(false),
-        _ => LispVal::Atom(input),
+        _ => LispVal::symbol(&input),
     }
 }
 
@@ -52,31 +123,251 @@ fn parse_atom(input: &str) -> IResult<&str, LispVal> {
 }
 
 fn symbol(s: &str) -> IResult<&str, &str> {
-    is_a("!#$%&|*+-/:<=>?@^_~")(s)
+    is_a("!#$%&*+-/:<=>?@^_~")(s)
 }
 
 #[test]
 fn atom_parser_test() {
     assert_eq!(
         parse_atom("$foo").unwrap(),
-        ("", LispVal::Atom("$foo".to_owned()))
+        ("", LispVal::symbol("$foo"))
     );
     assert_eq!(parse_atom("#f").unwrap(), ("", LispVal::Boolean(false)));
 }
 
+/// `#\a`, `#\space` or `#\x41`.
+fn parse_char(input: &str) -> IResult<&str, LispVal> {
+    let (input, _) = tag("#\\")(input)?;
+    let Some(first) = input.chars().next() else {
+        return error(input, ErrorKind::Char);
+    };
+    if !first.is_ascii_alphanumeric() {
+        return Ok((&input[first.len_utf8()..], LispVal::Char(first)));
+    }
+    let (rest, word) = alphanumeric1(input)?;
+    let c = if word.chars().count() == 1 {
+        Some(first)
+    } else if let Some(c) = named_char(word) {
+        Some(c)
+    } else {
+        word.strip_prefix('x')
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .and_then(char::from_u32)
+    };
+    match c {
+        Some(c) => Ok((rest, LispVal::Char(c))),
+        None => error(input, ErrorKind::Char),
+    }
+}
+
+#[test]
+fn char_parser_test() {
+    assert_eq!(parse_char("#\\a").unwrap(), ("", LispVal::Char('a')));
+    assert_eq!(parse_char("#\\space)").unwrap(), (")", LispVal::Char(' ')));
+    assert_eq!(parse_char("#\\x41").unwrap(), ("", LispVal::Char('A')));
+    assert_eq!(parse_char("#\\(").unwrap(), ("", LispVal::Char('(')));
+    assert!(parse_char("#\\bogus").is_err());
+    assert_eq!(LispVal::Char('\n').to_string(), "#\\newline");
+}
+
+/// `#| ... |#`, which may contain further block comments.
+fn block_comment(input: &str) -> IResult<&str, ()> {
+    let mut depth = 0;
+    let mut rest = input;
+    loop {
+        if let Some(r) = rest.strip_prefix("#|") {
+            depth += 1;
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix("|#") {
+            depth -= 1;
+            rest = r;
+            if depth == 0 {
+                return Ok((rest, ()));
+            }
+        } else {
+            let mut chars = rest.chars();
+            if chars.next().is_none() {
+                return error(input, ErrorKind::TakeUntil);
+            }
+            rest = chars.as_str();
+        }
+    }
+}
+
+/// Whitespace and comments: `;` to the end of the line, `#| ... |#` and `#;` followed by
+/// a datum to ignore.
+pub fn intertoken_space(mut input: &str) -> IResult<&str, ()> {
+    loop {
+        let trimmed = input.trim_start();
+        if let Some(rest) = trimmed.strip_prefix(';') {
+            input = rest.find('\n').map_or("", |i| &rest[i + 1..]);
+        } else if trimmed.starts_with("#|") {
+            input = block_comment(trimmed)?.0;
+        } else if let Some(rest) = trimmed.strip_prefix("#;") {
+            let (rest, _) = intertoken_space(rest)?;
+            input = parse_expr(rest)?.0;
+        } else {
+            return Ok((trimmed, ()));
+        }
+    }
+}
+
+/// Whether `input` stops in the middle of a datum, so that more text could complete it: an
+/// open list, string, `|symbol|` or block comment, or a prefix such as `'` still waiting for
+/// its datum. A stray `)` makes the input wrong however it continues.
+pub fn is_incomplete(input: &str) -> bool {
+    let mut depth = 0;
+    let mut pending = false;
+    let mut rest = input;
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        match c {
+            _ if c.is_whitespace() => continue,
+            ';' => {
+                rest = rest.find('\n').map_or("", |i| &rest[i..]);
+                continue;
+            }
+            '"' | '|' => {
+                let mut escaped = false;
+                let Some(end) = rest.find(|d| {
+                    let end = d == c && !escaped;
+                    escaped = d == '\\' && !escaped;
+                    end
+                }) else { return true };
+                rest = &rest[end + 1..];
+            }
+            '#' if rest.starts_with('|') => {
+                let Ok((after, _)) = block_comment(&input[input.len() - rest.len() - 1..]) else { return true };
+                rest = after;
+                continue;
+            }
+            '#' if rest.starts_with(';') => {
+                rest = &rest[1..];
+                pending = true;
+                continue;
+            }
+            '#' if rest.starts_with('\\') => {
+                let mut name = rest[1..].chars();
+                if name.next().is_none() {
+                    return true;
+                }
+                rest = name.as_str();
+            }
+            '#' if rest.starts_with(|d: char| d.is_ascii_digit()) => {
+                rest = rest.trim_start_matches(|d: char| d.is_ascii_digit());
+                if let Some(after) = rest.strip_prefix('=') {
+                    rest = after;
+                    pending = true;
+                    continue;
+                }
+            }
+            '\'' | '`' | ',' => {
+                pending = true;
+                continue;
+            }
+            '(' => depth += 1,
+            ')' if depth == 0 => return false,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        pending = false;
+    }
+    depth > 0 || pending
+}
+
+/// The data in `input`, with any whitespace and comments around them.
 pub fn parse_vector(input: &str) -> IResult<&str, Vec<LispVal>> {
-    separated_list0(multispace1, parse_expr)(input)
+    let (input, items) = many0(preceded(intertoken_space, parse_expr))(input)?;
+    let (input, _) = intertoken_space(input)?;
+    Ok((input, items))
+}
+
+/// The elements of a list, which share the labels of the datum they are part of.
+fn parse_items(input: &str) -> IResult<&str, Vec<LispVal>> {
+    let (input, items) = many0(preceded(intertoken_space, parse_datum))(input)?;
+    let (input, _) = intertoken_space(input)?;
+    Ok((input, items))
 }
 
 fn parse_quoted(input: &str) -> IResult<&str, LispVal> {
     let (input, _) = char('\'')(input)?;
-    parse_expr(input).map(|(i, l)| (i, LispVal::Quote(Box::new(l))))
+    parse_datum(input).map(|(i, l)| (i, LispVal::Quote(Box::new(l))))
+}
+
+thread_local! {
+    /// Data defined by `#n=` labels. While a labelled datum is being read its label maps to
+    /// a placeholder symbol, which is replaced by the datum once it is complete.
+    /// They are only visible within the top-level datum that defines them.
+    static LABELS: RefCell<HashMap<u64, LispVal>> = RefCell::new(HashMap::new());
+}
+
+/// Replaces `placeholder` inside `v` by `datum`, mutating pairs in place. Returns the
+/// replacement when `v` itself has to change, which for a pair it never does.
+fn replace_placeholder(v: &LispVal, placeholder: Symbol, datum: &LispVal, seen: &mut HashSet<*const Pair>) -> Option<LispVal> {
+    match v {
+        LispVal::Symbol(s) if *s == placeholder => Some(datum.clone()),
+        LispVal::Quote(q) => replace_placeholder(q, placeholder, datum, seen).map(|q| LispVal::Quote(Box::new(q))),
+        LispVal::Pair(_) => {
+            let mut v = v.clone();
+            while let LispVal::Pair(p) = v {
+                if !seen.insert(Rc::as_ptr(&p)) {
+                    break;
+                }
+                if let Some(car) = replace_placeholder(&p.car(), placeholder, datum, seen) {
+                    *p.car.borrow_mut() = car;
+                }
+                v = p.cdr();
+                if let Some(cdr) = replace_placeholder(&v, placeholder, datum, seen) {
+                    *p.cdr.borrow_mut() = cdr;
+                    break;
+                }
+            }
+            None
+        }
+        _ => None,
+    }
+}
+
+/// `#0=(a . #0#)`: a datum label, or a reference to one. Bad labels fail outright rather
+/// than letting `#0#` be read as a symbol.
+fn parse_label(input: &str) -> IResult<&str, LispVal> {
+    let (input, _) = char('#')(input)?;
+    let (input, n) = digit1(input)?;
+    let Ok(n) = n.parse::<u64>() else { return error(input, ErrorKind::Digit) };
+    if let Some(rest) = input.strip_prefix('#') {
+        return match LABELS.with(|l| l.borrow().get(&n).cloned()) {
+            Some(v) => Ok((rest, v)),
+            None => Err(nom::Err::Failure(Error::new(input, ErrorKind::Verify))),
+        };
+    }
+    let (input, _) = char('=')(input)?;
+    let placeholder = Symbol::unnamed();
+    LABELS.with(|l| l.borrow_mut().insert(n, LispVal::Symbol(placeholder)));
+    let (input, datum) = parse_datum(input)?;
+    // Only a pair can contain itself; `#0='#0#` has no finite representation.
+    if replace_placeholder(&datum, placeholder, &datum, &mut HashSet::new()).is_some() {
+        return Err(nom::Err::Failure(Error::new(input, ErrorKind::Verify)));
+    }
+    LABELS.with(|l| l.borrow_mut().insert(n, datum.clone()));
+    Ok((input, datum))
 }
 
+/// A top-level datum. Labels defined outside it are hidden while it is read, and the ones
+/// it defines are forgotten afterwards.
 pub fn parse_expr(input: &str) -> IResult<&str, LispVal> {
+    let outer = LABELS.with(|l| l.take());
+    let result = parse_datum(input);
+    LABELS.with(|l| *l.borrow_mut() = outer);
+    result
+}
+
+fn parse_datum(input: &str) -> IResult<&str, LispVal> {
     alt((
-        parse_atom,
+        parse_char,
+        parse_label,
         parse_number,
+        parse_atom,
+        parse_bar_symbol,
         parse_string,
         parse_quoted,
         parse_dotted_list,
@@ -85,54 +376,90 @@ pub fn parse_expr(input: &str) -> IResult<&str, LispVal> {
 }
 
 fn dotted(input: &str) -> IResult<&str, &str> {
-    let (input, _) = space0(input)?;
     let (input, _) = char('.')(input)?;
-    let (input, _) = space0(input)?;
+    let (input, _) = intertoken_space(input)?;
     Ok((input, "."))
 }
 
 fn parse_dotted_list(input: &str) -> IResult<&str, LispVal> {
     let (input, _) = char('(')(input)?;
-    let (input, (head, rest)) = separated_pair(parse_vector, dotted, parse_expr)(input)?;
+    let (input, (head, rest)) = separated_pair(parse_items, dotted, parse_datum)(input)?;
+    let (input, _) = intertoken_space(input)?;
     let (input, _) = char(')')(input)?;
-    Ok((input, LispVal::DottedList(head, Box::new(rest))))
+    Ok((input, LispVal::dotted_list(head, rest)))
 }
 
 fn parse_list(input: &str) -> IResult<&str, LispVal> {
     let (input, _) = char('(')(input)?;
-    let (input, items) = parse_vector(input)?;
+    let (input, items) = parse_items(input)?;
     let (input, _) = char(')')(input)?;
-    Ok((input, List(items)))
+    Ok((input, LispVal::list(items)))
 }
 
 #[test]
 fn list_parser_test() {
-    assert_eq!(
-        parse_list("$foo 42 53").unwrap(),
-        (
-            "",
-            LispVal::List(vec!(
-                LispVal::Atom("$foo".to_owned()),
-                LispVal::Number(42),
-                LispVal::Number(53)
-            ))
-        )
-    );
-    assert_eq!(
-        parse_list("\"foo\" 42 53").unwrap(),
-        (
-            "",
-            LispVal::List(vec!(
-                LispVal::LispString("foo".to_owned()),
-                LispVal::Number(42),
-                LispVal::Number(53)
-            ))
-        )
-    );
+    use crate::lispval::equal;
+    let (rest, v) = parse_list("($foo 42 53)").unwrap();
+    assert_eq!(rest, "");
+    assert!(equal(&v, &LispVal::list(vec!(
+        LispVal::symbol("$foo"),
+        LispVal::Number(42),
+        LispVal::Number(53)
+    ))));
+    let (rest, v) = parse_list("(\"foo\" 42 53)").unwrap();
+    assert_eq!(rest, "");
+    assert!(equal(&v, &LispVal::list(vec!(
+        LispVal::LispString("foo".to_owned()),
+        LispVal::Number(42),
+        LispVal::Number(53)
+    ))));
 }
 
 #[test]
 fn quoted_parser_test() {
     let output = parse_quoted("'52").unwrap();
     assert_eq!(output, ("", LispVal::Quote(Box::new(LispVal::Number(52)))));
-}
\ No newline at end of file
+}
+#[test]
+fn dotted_list_parser_test() {
+    let (_, output) = parse_expr("(1 2 . 3)").unwrap();
+    let expected = LispVal::dotted_list(vec![LispVal::Number(1), LispVal::Number(2)], LispVal::Number(3));
+    assert!(crate::lispval::equal(&output, &expected));
+    assert_eq!(output.to_string(), "(1 2 . 3)");
+    assert_eq!(parse_expr("()").unwrap(), ("", LispVal::Nil));
+}
+
+#[test]
+fn datum_label_parser_test() {
+    let (_, v) = parse_expr("#0=(a b . #0#)").unwrap();
+    let LispVal::Pair(p) = &v else { panic!() };
+    let LispVal::Pair(cdr) = p.cdr() else { panic!() };
+    assert!(matches!(cdr.cdr(), LispVal::Pair(q) if Rc::ptr_eq(&q, p)));
+    assert_eq!(v.to_string(), "#0=(a b . #0#)");
+    let (_, v) = parse_expr("(#1=(x) #1#)").unwrap();
+    assert_eq!(v.to_string(), "((x) (x))");
+    assert!(parse_expr("#0='#0#").is_err());
+    assert!(parse_expr("#7#").is_err());
+    assert!(parse_expr("#0=(x)").is_ok());
+    assert!(parse_expr("#0#").is_err());
+    assert!(parse_vector("#0=(a) (#0#)").is_err());
+}
+
+#[test]
+fn incomplete_input_test() {
+    for s in ["(1 2", "\"a (", "'", "(a #| b", "#0=", "#;", "#\\", "|a b", "(#\\) ; )"] {
+        assert!(is_incomplete(s), "{}", s);
+    }
+    for s in ["", "(1 2)", ")", "(a)) (", "\"a\\\" (\" x", "#\\(", "#0#", "; (", "#| ( |#"] {
+        assert!(!is_incomplete(s), "{}", s);
+    }
+}
+
+#[test]
+fn comment_parser_test() {
+    let (rest, items) = parse_vector(" ; leading\n(1 ; inner\n 2 #| block #| nested |# |# 3 #;(skipped) 4\n) #;5 x ; end").unwrap();
+    assert_eq!(rest, "");
+    assert_eq!(items.iter().map(|v| v.to_string()).collect::<Vec<_>>(), ["(1 2 3 4)", "x"]);
+    assert_eq!(parse_expr("( a . b )").unwrap().1.to_string(), "(a . b)");
+    assert_eq!(parse_vector("(1 #| open").unwrap().0, "(1 #| open");
+}
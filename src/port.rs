@@ -0,0 +1,703 @@
+use std::cell::{Cell, RefCell};
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::rc::Rc;
+use std::thread::LocalKey;
+
+use crate::env::Env;
+use crate::error::{io_error, LispErr};
+use crate::error::LispErr::{Runtime, WrongType};
+use crate::evaluation::call_function;
+use crate::lispval::{LispVal, PrimitiveFn};
+use crate::parameter::{with_parameters, Parameter};
+use crate::parser::{intertoken_space, is_incomplete, parse_expr};
+use crate::printer::{print_string, Mode};
+use crate::lispval::LispVal::{Boolean, Eof, LispString, Number, PrimitiveFunc, Unspecified};
+use crate::primitive_functions::{expect_args, expect_args_between};
+
+enum PortKind {
+    /// Text is pulled from `source` a line at a time into `buffer`; `pos` is the first
+    /// character of `buffer` that has not been consumed yet.
+    Input { source: Box<dyn BufRead>, buffer: String, pos: usize, interactive: bool },
+    Output(Box<dyn Write>),
+    StringOutput(String),
+    BinaryInput(Box<dyn BufRead>),
+    BinaryOutput(Box<dyn Write>),
+    Closed,
+}
+
+/// An R7RS port. Every port is either an input or an output port, and either textual or
+/// binary, and stays so after it is closed.
+pub struct Port {
+    pub name: String,
+    input: bool,
+    binary: bool,
+    kind: RefCell<PortKind>,
+    /// Whether nothing has been written yet or the last text written ended a line.
+    line_start: Cell<bool>,
+}
+
+impl Debug for Port {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Port({})", self.name)
+    }
+}
+
+impl Port {
+    fn new(name: &str, input: bool, kind: PortKind) -> Port {
+        let binary = matches!(kind, PortKind::BinaryInput(_) | PortKind::BinaryOutput(_));
+        Port { name: name.to_string(), input, binary, kind: RefCell::new(kind), line_start: Cell::new(true) }
+    }
+
+    pub fn input(name: &str, source: Box<dyn BufRead>) -> Port {
+        Port::new(name, true, PortKind::Input { source, buffer: String::new(), pos: 0, interactive: false })
+    }
+
+    pub fn output(name: &str, sink: Box<dyn Write>) -> Port {
+        Port::new(name, false, PortKind::Output(sink))
+    }
+
+    pub fn stdin() -> Port {
+        let source = Box::new(BufReader::new(std::io::stdin()));
+        Port::new("stdin", true, PortKind::Input { source, buffer: String::new(), pos: 0, interactive: true })
+    }
+
+    /// An input port reading the characters of `s`.
+    pub fn string_input(s: &str) -> Port {
+        let source = Box::new(std::io::empty());
+        Port::new("string", true, PortKind::Input { source, buffer: s.to_string(), pos: 0, interactive: false })
+    }
+
+    pub fn string_output() -> Port {
+        Port::new("string", false, PortKind::StringOutput(String::new()))
+    }
+
+    pub fn open_input_file(path: &str) -> Result<Port, LispErr> {
+        let file = File::open(path).map_err(|e| io_error(path, e))?;
+        Ok(Port::input(path, Box::new(BufReader::new(file))))
+    }
+
+    pub fn open_output_file(path: &str) -> Result<Port, LispErr> {
+        let file = File::create(path).map_err(|e| io_error(path, e))?;
+        Ok(Port::output(path, Box::new(BufWriter::new(file))))
+    }
+
+    pub fn open_binary_input_file(path: &str) -> Result<Port, LispErr> {
+        let file = File::open(path).map_err(|e| io_error(path, e))?;
+        Ok(Port::new(path, true, PortKind::BinaryInput(Box::new(BufReader::new(file)))))
+    }
+
+    pub fn open_binary_output_file(path: &str) -> Result<Port, LispErr> {
+        let file = File::create(path).map_err(|e| io_error(path, e))?;
+        Ok(Port::new(path, false, PortKind::BinaryOutput(Box::new(BufWriter::new(file)))))
+    }
+
+    pub fn is_input(&self) -> bool {
+        self.input
+    }
+
+    pub fn is_binary(&self) -> bool {
+        self.binary
+    }
+
+    pub fn is_open(&self) -> bool {
+        !matches!(*self.kind.borrow(), PortKind::Closed)
+    }
+
+    /// Flushes pending output and releases the underlying file. Closing twice is allowed.
+    pub fn close(&self) -> Result<(), LispErr> {
+        self.flush()?;
+        self.kind.replace(PortKind::Closed);
+        Ok(())
+    }
+
+    fn error(&self, e: std::io::Error) -> LispErr {
+        io_error(&self.name, e)
+    }
+
+    fn closed(&self) -> LispErr {
+        Runtime(format!("Port {} is closed", self.name))
+    }
+
+    fn wrong_kind(&self) -> LispErr {
+        let direction = if self.input { "input" } else { "output" };
+        let kind = if self.binary { "binary" } else { "textual" };
+        Runtime(format!("Port {} is a {} {} port", self.name, kind, direction))
+    }
+
+    pub fn write_str(&self, s: &str) -> Result<(), LispErr> {
+        match &mut *self.kind.borrow_mut() {
+            PortKind::Output(w) => w.write_all(s.as_bytes()).map_err(|e| self.error(e))?,
+            PortKind::StringOutput(out) => out.push_str(s),
+            PortKind::Closed => return Err(self.closed()),
+            _ => return Err(self.wrong_kind()),
+        }
+        if !s.is_empty() {
+            self.line_start.set(s.ends_with('\n'));
+        }
+        Ok(())
+    }
+
+    /// Whether the next character written would start a line, as far as this port knows.
+    pub fn at_line_start(&self) -> bool {
+        self.line_start.get()
+    }
+
+    pub fn write_u8(&self, b: u8) -> Result<(), LispErr> {
+        match &mut *self.kind.borrow_mut() {
+            PortKind::BinaryOutput(w) => w.write_all(&[b]).map_err(|e| self.error(e)),
+            PortKind::Closed => Err(self.closed()),
+            _ => Err(self.wrong_kind()),
+        }
+    }
+
+    pub fn flush(&self) -> Result<(), LispErr> {
+        match &mut *self.kind.borrow_mut() {
+            PortKind::Output(w) | PortKind::BinaryOutput(w) => w.flush().map_err(|e| self.error(e)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Text written to a string output port so far.
+    pub fn output_string(&self) -> Result<String, LispErr> {
+        match &*self.kind.borrow() {
+            PortKind::StringOutput(out) => Ok(out.clone()),
+            PortKind::Closed => Err(self.closed()),
+            _ => Err(Runtime(format!("Port {} is not a string output port", self.name))),
+        }
+    }
+
+    /// Runs `f` on the unconsumed input, reading another line from the source first when
+    /// everything buffered has been consumed. `f` sees an empty string at end of file.
+    fn with_buffer<T>(&self, f: impl FnOnce(&str, &mut usize) -> T) -> Result<T, LispErr> {
+        match &mut *self.kind.borrow_mut() {
+            PortKind::Input { source, buffer, pos, .. } => {
+                if *pos >= buffer.len() {
+                    buffer.clear();
+                    *pos = 0;
+                    source.read_line(buffer).map_err(|e| self.error(e))?;
+                }
+                let mut consumed = 0;
+                let result = f(&buffer[*pos..], &mut consumed);
+                *pos += consumed;
+                Ok(result)
+            }
+            PortKind::Closed => Err(self.closed()),
+            _ => Err(self.wrong_kind()),
+        }
+    }
+
+    /// Next byte of a binary input port, consumed only if `consume` is set.
+    fn next_byte(&self, consume: bool) -> Result<Option<u8>, LispErr> {
+        match &mut *self.kind.borrow_mut() {
+            PortKind::BinaryInput(source) => {
+                let b = source.fill_buf().map_err(|e| self.error(e))?.first().copied();
+                if consume && b.is_some() {
+                    source.consume(1);
+                }
+                Ok(b)
+            }
+            PortKind::Closed => Err(self.closed()),
+            _ => Err(self.wrong_kind()),
+        }
+    }
+
+    pub fn read_u8(&self) -> Result<Option<u8>, LispErr> {
+        self.next_byte(true)
+    }
+
+    pub fn peek_u8(&self) -> Result<Option<u8>, LispErr> {
+        self.next_byte(false)
+    }
+
+    pub fn peek_char(&self) -> Result<Option<char>, LispErr> {
+        self.with_buffer(|rest, _| rest.chars().next())
+    }
+
+    pub fn read_char(&self) -> Result<Option<char>, LispErr> {
+        self.with_buffer(|rest, consumed| {
+            let c = rest.chars().next();
+            *consumed = c.map_or(0, |c| c.len_utf8());
+            c
+        })
+    }
+
+    /// Reads up to the next newline, which is consumed but not returned.
+    pub fn read_line(&self) -> Result<Option<String>, LispErr> {
+        let mut line = String::new();
+        loop {
+            let (chunk, newline) = self.with_buffer(|rest, consumed| match rest.find('\n') {
+                Some(i) => {
+                    *consumed = i + 1;
+                    (rest[..i].to_string(), true)
+                }
+                None => {
+                    *consumed = rest.len();
+                    (rest.to_string(), false)
+                }
+            })?;
+            line.push_str(&chunk);
+            if newline {
+                return Ok(Some(line.trim_end_matches('\r').to_string()));
+            }
+            if chunk.is_empty() {
+                return Ok(if line.is_empty() { None } else { Some(line) });
+            }
+        }
+    }
+
+    /// Parses the next datum, pulling in more lines until it is complete. A datum that
+    /// runs up to the end of the buffer might continue on the next line, so it is only
+    /// accepted once more input or end of file follows it. Text that no further input could
+    /// make valid, like a stray `)`, fails straight away.
+    pub fn read_datum(&self) -> Result<LispVal, LispErr> {
+        match &mut *self.kind.borrow_mut() {
+            PortKind::Input { source, buffer, pos, .. } => loop {
+                // An unterminated block comment is left for the parser to fail on, so more is read.
+                let rest = match intertoken_space(&buffer[*pos..]) {
+                    Ok((rest, _)) => rest,
+                    Err(_) => buffer[*pos..].trim_start(),
+                };
+                *pos = buffer.len() - rest.len();
+                let parsed = match parse_expr(rest) {
+                    Ok((remaining, v)) if !remaining.is_empty() => {
+                        *pos = buffer.len() - remaining.len();
+                        return Ok(v);
+                    }
+                    Ok((_, v)) => Ok(v),
+                    Err(e) if !rest.is_empty() && !is_incomplete(rest) => {
+                        // No more input can fix it, so the rest of its line is dropped.
+                        *pos += rest.find('\n').map_or(rest.len(), |i| i + 1);
+                        return Err(Runtime(format!("{}: {}", self.name, e)));
+                    }
+                    Err(e) => Err(e.to_string()),
+                };
+                buffer.drain(..*pos);
+                *pos = 0;
+                if source.read_line(buffer).map_err(|e| self.error(e))? > 0 {
+                    continue;
+                }
+                return match parsed {
+                    _ if buffer.is_empty() => Ok(Eof),
+                    Ok(v) => {
+                        *pos = buffer.len();
+                        Ok(v)
+                    }
+                    Err(e) => Err(Runtime(format!("{}: {}", self.name, e))),
+                };
+            },
+            PortKind::Closed => Err(self.closed()),
+            _ => Err(self.wrong_kind()),
+        }
+    }
+
+    /// Everything up to end of file.
+    pub fn read_to_end(&self) -> Result<String, LispErr> {
+        let mut s = String::new();
+        loop {
+            let chunk = self.with_buffer(|rest, consumed| {
+                *consumed = rest.len();
+                rest.to_string()
+            })?;
+            if chunk.is_empty() {
+                return Ok(s);
+            }
+            s.push_str(&chunk);
+        }
+    }
+
+    pub fn read_string(&self, k: usize) -> Result<Option<String>, LispErr> {
+        let mut s = String::new();
+        for _ in 0..k {
+            match self.read_char()? {
+                Some(c) => s.push(c),
+                None => break,
+            }
+        }
+        Ok(if s.is_empty() && k > 0 { None } else { Some(s) })
+    }
+
+    /// Whether a character can be read without blocking. Only interactive ports with an
+    /// empty buffer may block.
+    pub fn char_ready(&self) -> Result<bool, LispErr> {
+        match &*self.kind.borrow() {
+            PortKind::Input { buffer, pos, interactive, .. } => Ok(*pos < buffer.len() || !interactive),
+            PortKind::BinaryInput(_) => Ok(true),
+            PortKind::Closed => Err(self.closed()),
+            _ => Err(self.wrong_kind()),
+        }
+    }
+}
+
+/// Converter of the current input port parameter.
+fn expect_input_port(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 1)?;
+    match &a[0] {
+        LispVal::Port(p) if p.input => Ok(a[0].clone()),
+        v => Err(WrongType("input port".to_string(), v.clone())),
+    }
+}
+
+/// Converter of the current output and error port parameters.
+fn expect_output_port(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 1)?;
+    match &a[0] {
+        LispVal::Port(p) if !p.input => Ok(a[0].clone()),
+        v => Err(WrongType("output port".to_string(), v.clone())),
+    }
+}
+
+fn port_parameter(port: Port, converter: PrimitiveFn) -> Rc<Parameter> {
+    Parameter::new(LispVal::Port(Rc::new(port)), Some(PrimitiveFunc { name: None, func: converter }))
+}
+
+thread_local! {
+    static CURRENT_INPUT: Rc<Parameter> = port_parameter(Port::stdin(), expect_input_port);
+    static CURRENT_OUTPUT: Rc<Parameter> = port_parameter(Port::output("stdout", Box::new(std::io::stdout())), expect_output_port);
+    static CURRENT_ERROR: Rc<Parameter> = port_parameter(Port::output("stderr", Box::new(std::io::stderr())), expect_output_port);
+}
+
+fn current(parameter: &'static LocalKey<Rc<Parameter>>) -> Rc<Port> {
+    match parameter.with(|p| p.get()) {
+        LispVal::Port(p) => p,
+        _ => unreachable!("the converters only accept ports"),
+    }
+}
+
+pub fn current_input() -> Rc<Port> {
+    current(&CURRENT_INPUT)
+}
+
+pub fn current_output() -> Rc<Port> {
+    current(&CURRENT_OUTPUT)
+}
+
+pub fn current_error() -> Rc<Port> {
+    current(&CURRENT_ERROR)
+}
+
+/// Makes `port` the current input port while `f` runs, restoring the previous port
+/// afterwards whether or not `f` succeeds.
+pub fn with_current_input<T>(port: Rc<Port>, f: impl FnOnce() -> T) -> T {
+    with_parameters(vec![(CURRENT_INPUT.with(Rc::clone), LispVal::Port(port))], f)
+}
+
+/// Makes `port` the current output port while `f` runs, restoring the previous port
+/// afterwards whether or not `f` succeeds.
+pub fn with_current_output<T>(port: Rc<Port>, f: impl FnOnce() -> T) -> T {
+    with_parameters(vec![(CURRENT_OUTPUT.with(Rc::clone), LispVal::Port(port))], f)
+}
+
+fn port(v: &LispVal) -> Result<Rc<Port>, LispErr> {
+    match v {
+        LispVal::Port(p) => Ok(p.clone()),
+        _ => Err(Runtime(format!("Expected port but got {}", v))),
+    }
+}
+
+fn input_port(a: &[LispVal], i: usize) -> Result<Rc<Port>, LispErr> {
+    a.get(i).map(port).unwrap_or_else(|| Ok(current_input()))
+}
+
+pub fn output_port(a: &[LispVal], i: usize) -> Result<Rc<Port>, LispErr> {
+    a.get(i).map(port).unwrap_or_else(|| Ok(current_output()))
+}
+
+fn char_or_eof(c: Option<char>) -> LispVal {
+    c.map_or(Eof, LispVal::Char)
+}
+
+fn print_to_port(a: &[LispVal], mode: Mode) -> Result<LispVal, LispErr> {
+    expect_args_between(a, 1, 2)?;
+    output_port(a, 1)?.write_str(&print_string(&a[0], mode))?;
+    Ok(Unspecified)
+}
+
+/// `(write-string string [port [start [end]]])`
+fn write_string(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args_between(a, 1, 4)?;
+    let LispString(s) = &a[0] else {
+        return Err(Runtime(format!("Expected string but got {}", a[0])));
+    };
+    let chars: Vec<char> = s.chars().collect();
+    let start = a.get(2).map(|v| v.num()).transpose()?.unwrap_or(0) as usize;
+    let end = a.get(3).map(|v| v.num()).transpose()?.unwrap_or(chars.len() as i64) as usize;
+    if start > end || end > chars.len() {
+        return Err(Runtime(format!("Invalid range {}..{} for string of length {}", start, end, chars.len())));
+    }
+    output_port(a, 1)?.write_str(&chars[start..end].iter().collect::<String>())?;
+    Ok(Unspecified)
+}
+
+fn write_char(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args_between(a, 1, 2)?;
+    let LispVal::Char(c) = a[0] else {
+        return Err(Runtime(format!("Expected character but got {}", a[0])));
+    };
+    output_port(a, 1)?.write_str(&c.to_string())?;
+    Ok(Unspecified)
+}
+
+fn newline(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args_between(a, 0, 1)?;
+    output_port(a, 0)?.write_str("\n")?;
+    Ok(Unspecified)
+}
+
+fn flush_output_port(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args_between(a, 0, 1)?;
+    output_port(a, 0)?.flush()?;
+    Ok(Unspecified)
+}
+
+fn read_char(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args_between(a, 0, 1)?;
+    Ok(char_or_eof(input_port(a, 0)?.read_char()?))
+}
+
+fn peek_char(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args_between(a, 0, 1)?;
+    Ok(char_or_eof(input_port(a, 0)?.peek_char()?))
+}
+
+fn read_line(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args_between(a, 0, 1)?;
+    Ok(input_port(a, 0)?.read_line()?.map_or(Eof, LispString))
+}
+
+/// `(read-string k [port])`
+fn read_string(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args_between(a, 1, 2)?;
+    let k = a[0].num()?;
+    if k < 0 {
+        return Err(Runtime(format!("Expected non-negative count but got {}", k)));
+    }
+    Ok(input_port(a, 1)?.read_string(k as usize)?.map_or(Eof, LispString))
+}
+
+fn char_ready(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args_between(a, 0, 1)?;
+    Ok(Boolean(input_port(a, 0)?.char_ready()?))
+}
+
+fn read_u8(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args_between(a, 0, 1)?;
+    Ok(input_port(a, 0)?.read_u8()?.map_or(Eof, |b| Number(b as i64)))
+}
+
+fn peek_u8(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args_between(a, 0, 1)?;
+    Ok(input_port(a, 0)?.peek_u8()?.map_or(Eof, |b| Number(b as i64)))
+}
+
+fn write_u8(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args_between(a, 1, 2)?;
+    let b = u8::try_from(a[0].num()?).map_err(|_| Runtime(format!("Expected byte but got {}", a[0])))?;
+    output_port(a, 1)?.write_u8(b)?;
+    Ok(Unspecified)
+}
+
+fn open_with(a: &[LispVal], open: fn(&str) -> Result<Port, LispErr>) -> Result<LispVal, LispErr> {
+    expect_args(a, 1)?;
+    Ok(LispVal::Port(Rc::new(open(&a[0].str()?)?)))
+}
+
+fn close_port(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 1)?;
+    port(&a[0])?.close()?;
+    Ok(Unspecified)
+}
+
+/// Calls `(proc port)` and closes the port once `proc` returns or fails.
+fn call_with_port(port: Rc<Port>, proc: &LispVal, env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    let result = call_function(proc, &[LispVal::Port(port.clone())], env);
+    port.close()?;
+    result
+}
+
+fn call_with_input_file(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 2)?;
+    call_with_port(Rc::new(Port::open_input_file(&a[0].str()?)?), &a[1], env)
+}
+
+fn call_with_output_file(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 2)?;
+    call_with_port(Rc::new(Port::open_output_file(&a[0].str()?)?), &a[1], env)
+}
+
+fn with_input_from_file(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 2)?;
+    let port = Rc::new(Port::open_input_file(&a[0].str()?)?);
+    let result = with_current_input(port.clone(), || call_function(&a[1], &[], env));
+    port.close()?;
+    result
+}
+
+fn with_output_to_file(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 2)?;
+    let port = Rc::new(Port::open_output_file(&a[0].str()?)?);
+    let result = with_current_output(port.clone(), || call_function(&a[1], &[], env));
+    port.close()?;
+    result
+}
+
+fn read(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args_between(a, 0, 1)?;
+    input_port(a, 0)?.read_datum()
+}
+
+fn get_output_string(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 1)?;
+    Ok(LispString(port(&a[0])?.output_string()?))
+}
+
+/// `(call-with-output-string proc)` calls `(proc port)` and returns what was written.
+fn call_with_output_string(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 1)?;
+    let port = Rc::new(Port::string_output());
+    call_function(&a[0], &[LispVal::Port(port.clone())], env)?;
+    Ok(LispString(port.output_string()?))
+}
+
+fn with_output_to_string(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 1)?;
+    let port = Rc::new(Port::string_output());
+    with_current_output(port.clone(), || call_function(&a[0], &[], env))?;
+    Ok(LispString(port.output_string()?))
+}
+
+pub fn define_port_functions(e: &mut Env) {
+    e.define("current-input-port", LispVal::Parameter(CURRENT_INPUT.with(Rc::clone))).unwrap();
+    e.define("current-output-port", LispVal::Parameter(CURRENT_OUTPUT.with(Rc::clone))).unwrap();
+    e.define("current-error-port", LispVal::Parameter(CURRENT_ERROR.with(Rc::clone))).unwrap();
+    e.define_primitive("port?", |a, _| { expect_args(a, 1)?; Ok(Boolean(matches!(a[0], LispVal::Port(_)))) }).unwrap();
+    e.define_primitive("input-port?", |a, _| { expect_args(a, 1)?; Ok(Boolean(matches!(&a[0], LispVal::Port(p) if p.is_input()))) }).unwrap();
+    e.define_primitive("output-port?", |a, _| { expect_args(a, 1)?; Ok(Boolean(matches!(&a[0], LispVal::Port(p) if !p.is_input()))) }).unwrap();
+    e.define_primitive("eof-object", |a, _| { expect_args(a, 0)?; Ok(Eof) }).unwrap();
+    e.define_primitive("eof-object?", |a, _| { expect_args(a, 1)?; Ok(Boolean(a[0] == Eof)) }).unwrap();
+    e.define_primitive("char?", |a, _| { expect_args(a, 1)?; Ok(Boolean(matches!(a[0], LispVal::Char(_)))) }).unwrap();
+    e.define_primitive("display", |a, _| print_to_port(a, Mode::Display)).unwrap();
+    e.define_primitive("write", |a, _| print_to_port(a, Mode::Write)).unwrap();
+    e.define_primitive("write-shared", |a, _| print_to_port(a, Mode::WriteShared)).unwrap();
+    e.define_primitive("write-simple", |a, _| print_to_port(a, Mode::WriteSimple)).unwrap();
+    e.define_primitive("write-string", write_string).unwrap();
+    e.define_primitive("write-char", write_char).unwrap();
+    e.define_primitive("newline", newline).unwrap();
+    e.define_primitive("flush-output-port", flush_output_port).unwrap();
+    e.define_primitive("read-char", read_char).unwrap();
+    e.define_primitive("peek-char", peek_char).unwrap();
+    e.define_primitive("read-line", read_line).unwrap();
+    e.define_primitive("read-string", read_string).unwrap();
+    e.define_primitive("char-ready?", char_ready).unwrap();
+    e.define_primitive("with-output-to-string", with_output_to_string).unwrap();
+    e.define_primitive("open-input-string", |a, _| { expect_args(a, 1)?; Ok(LispVal::Port(Rc::new(Port::string_input(&a[0].str()?)))) }).unwrap();
+    e.define_primitive("open-output-string", |a, _| { expect_args(a, 0)?; Ok(LispVal::Port(Rc::new(Port::string_output()))) }).unwrap();
+    e.define_primitive("get-output-string", get_output_string).unwrap();
+    e.define_primitive("call-with-output-string", call_with_output_string).unwrap();
+    e.define_primitive("read", read).unwrap();
+    e.define_primitive("textual-port?", |a, _| { expect_args(a, 1)?; Ok(Boolean(matches!(&a[0], LispVal::Port(p) if !p.is_binary()))) }).unwrap();
+    e.define_primitive("binary-port?", |a, _| { expect_args(a, 1)?; Ok(Boolean(matches!(&a[0], LispVal::Port(p) if p.is_binary()))) }).unwrap();
+    e.define_primitive("input-port-open?", |a, _| { expect_args(a, 1)?; let p = port(&a[0])?; Ok(Boolean(p.is_input() && p.is_open())) }).unwrap();
+    e.define_primitive("output-port-open?", |a, _| { expect_args(a, 1)?; let p = port(&a[0])?; Ok(Boolean(!p.is_input() && p.is_open())) }).unwrap();
+    e.define_primitive("open-input-file", |a, _| open_with(a, Port::open_input_file)).unwrap();
+    e.define_primitive("open-output-file", |a, _| open_with(a, Port::open_output_file)).unwrap();
+    e.define_primitive("open-binary-input-file", |a, _| open_with(a, Port::open_binary_input_file)).unwrap();
+    e.define_primitive("open-binary-output-file", |a, _| open_with(a, Port::open_binary_output_file)).unwrap();
+    e.define_primitive("close-port", close_port).unwrap();
+    e.define_primitive("close-input-port", close_port).unwrap();
+    e.define_primitive("close-output-port", close_port).unwrap();
+    e.define_primitive("call-with-input-file", call_with_input_file).unwrap();
+    e.define_primitive("call-with-output-file", call_with_output_file).unwrap();
+    e.define_primitive("with-input-from-file", with_input_from_file).unwrap();
+    e.define_primitive("with-output-to-file", with_output_to_file).unwrap();
+    e.define_primitive("read-u8", read_u8).unwrap();
+    e.define_primitive("peek-u8", peek_u8).unwrap();
+    e.define_primitive("u8-ready?", char_ready).unwrap();
+    e.define_primitive("write-u8", write_u8).unwrap();
+}
+
+#[test]
+fn output_port_test() {
+    use crate::evaluation::eval_str;
+    let env = &crate::primitive_functions::create_eden_env();
+    let run = |s: &str| eval_str(s, env).unwrap().to_string();
+    assert_eq!(eval_str("(with-output-to-string (lambda () (display \"a\") (write \"b\") (newline) (write-char #\\c)))", env).unwrap(),
+               LispString("a\"b\"\nc".to_string()));
+    assert_eq!(run("(with-output-to-string (lambda () (write-string \"hello\" (current-output-port) 1 3)))"), "\"el\"");
+    assert!(eval_str("(with-output-to-string (lambda () (display \"a\") (car '())))", env).is_err());
+    assert_eq!(run("(with-output-to-string (lambda () (display 1)))"), "\"1\"");
+}
+
+#[test]
+fn input_port_test() {
+    let port = Port::input("test", Box::new("ab\ncd\n\nlast".as_bytes()));
+    assert_eq!(port.peek_char().unwrap(), Some('a'));
+    assert_eq!(port.read_char().unwrap(), Some('a'));
+    assert_eq!(port.read_line().unwrap(), Some("b".to_string()));
+    assert_eq!(port.read_string(3).unwrap(), Some("cd\n".to_string()));
+    assert_eq!(port.read_line().unwrap(), Some("".to_string()));
+    assert_eq!(port.read_line().unwrap(), Some("last".to_string()));
+    assert_eq!(port.read_line().unwrap(), None);
+    assert_eq!(port.read_char().unwrap(), None);
+}
+
+#[test]
+fn file_port_test() {
+    use crate::evaluation::eval_str;
+    let env = &crate::primitive_functions::create_eden_env();
+    let path = std::env::temp_dir().join(format!("lisp-port-test-{}", std::process::id()));
+    env.borrow_mut().define("path", LispString(path.to_string_lossy().into_owned())).unwrap();
+    let run = |s: &str| eval_str(s, env).unwrap().to_string();
+    run("(define p (open-output-file path))");
+    run("(write-string \"first\" p)");
+    run("(newline p)");
+    run("(write-string \"second\" p)");
+    run("(close-port p)");
+    assert_eq!(run("(output-port-open? p)"), "#f");
+    assert!(eval_str("(write-char #\\a p)", env).is_err());
+    assert_eq!(run("(call-with-input-file path read-line)"), "\"first\"");
+    assert_eq!(run("(with-input-from-file path (lambda () (read-line) (read-line)))"), "\"second\"");
+    run("(define p (open-binary-output-file path))");
+    run("(write-u8 1 p)");
+    run("(write-u8 255 p)");
+    run("(close-output-port p)");
+    run("(define p (open-binary-input-file path))");
+    assert_eq!(run("(binary-port? p)"), "#t");
+    assert_eq!(run("(peek-u8 p)"), "1");
+    assert_eq!(run("(read-u8 p)"), "1");
+    assert_eq!(run("(read-u8 p)"), "255");
+    assert_eq!(run("(eof-object? (read-u8 p))"), "#t");
+    assert!(eval_str("(read-char p)", env).is_err());
+    std::fs::remove_file(&path).unwrap();
+    assert!(matches!(eval_str("(open-input-file path)", env), Err(LispErr::Io(..))));
+}
+
+#[test]
+fn string_port_test() {
+    use crate::evaluation::eval_str;
+    let env = &crate::primitive_functions::create_eden_env();
+    let run = |s: &str| eval_str(s, env).unwrap().to_string();
+    run("(define p (open-input-string \"(a b . c) 42 foo\"))");
+    assert_eq!(run("(read p)"), "(a b . c)");
+    assert_eq!(run("(read-char p)"), "#\\space");
+    assert_eq!(run("(read p)"), "42");
+    assert_eq!(run("(read p)"), "foo");
+    assert_eq!(run("(eof-object? (read p))"), "#t");
+    run("(define o (open-output-string))");
+    run("(write 'x o)");
+    run("(display \"y\" o)");
+    assert_eq!(run("(get-output-string o)"), "\"xy\"");
+    assert_eq!(run("(call-with-output-string (lambda (p) (write-char #\\z p)))"), "\"z\"");
+    assert!(eval_str("(read (open-input-string \"(1 2\"))", env).is_err());
+
+    let port = Port::input("lines", Box::new("(1\n 2)\n3\n".as_bytes()));
+    assert_eq!(port.read_datum().unwrap().to_string(), "(1 2)");
+    assert_eq!(port.read_datum().unwrap().to_string(), "3");
+    assert_eq!(port.read_datum().unwrap(), Eof);
+
+    let port = Port::input("lines", Box::new("(1 2))\n(3\n 4)\n".as_bytes()));
+    assert_eq!(port.read_datum().unwrap().to_string(), "(1 2)");
+    assert!(port.read_datum().is_err());
+    assert_eq!(port.read_datum().unwrap().to_string(), "(3 4)");
+}
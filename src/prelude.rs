@@ -0,0 +1,61 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::env::Env;
+use crate::error::LispErr;
+use crate::evaluation::eval;
+use crate::lispval::LispVal;
+use crate::port::Port;
+use crate::primitive_functions::create_eden_env;
+
+const PRELUDE: &str = include_str!("prelude.scm");
+
+/// Evaluates every datum in `port` in `env`, returning the value of the last one.
+pub fn eval_port(port: &Port, env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    let mut result = LispVal::Unspecified;
+    loop {
+        match port.read_datum()? {
+            LispVal::Eof => return Ok(result),
+            v => result = eval(&v, env)?,
+        }
+    }
+}
+
+/// Loads the prelude into `env`: the file named by `LISP_PRELUDE` if it is set, and the
+/// one compiled into the interpreter otherwise.
+pub fn load_prelude(env: &Rc<RefCell<Env>>) -> Result<(), LispErr> {
+    let port = match std::env::var("LISP_PRELUDE") {
+        Ok(path) => Port::open_input_file(&path)?,
+        Err(_) => Port::string_input(PRELUDE),
+    };
+    eval_port(&port, env)?;
+    Ok(())
+}
+
+/// A new environment with the built-in procedures and the prelude.
+pub fn standard_env() -> Result<Rc<RefCell<Env>>, LispErr> {
+    let env = create_eden_env();
+    load_prelude(&env)?;
+    Ok(env)
+}
+
+#[test]
+fn prelude_test() {
+    use crate::evaluation::eval_str;
+    let env = &standard_env().unwrap();
+    let run = |s: &str| eval_str(s, env).unwrap().to_string();
+    assert_eq!(run("(not 5)"), "#f");
+    assert_eq!(run("(null? '())"), "#t");
+    assert_eq!(run("(list (abs -3) (square 4) (odd? -3) (gcd 12 18) (lcm 4 6) (max 1 5 2))"), "(3 16 #t 6 12 5)");
+    assert_eq!(run("(caddr '(1 2 3 4))"), "3");
+    assert_eq!(run("(find even? '(1 3 4 5))"), "4");
+    assert_eq!(run("(find-tail even? '(1 3))"), "#f");
+    assert_eq!(run("(count odd? '(1 2 3))"), "2");
+    assert_eq!(run("(filter-map (lambda (x) (if (odd? x) (* x 10) #f)) '(1 2 3))"), "(10 30)");
+    assert_eq!(run("((compose square +) 1 2)"), "9");
+    assert_eq!(run("((complement zero?) 0)"), "#f");
+    assert_eq!(run("(foldl - 0 '(1 2 3))"), "-6");
+    run("(define l (list-copy '(1 2 3)))");
+    run("(list-set! l 1 'x)");
+    assert_eq!(run("l"), "(1 x 3)");
+}
@@ -0,0 +1,86 @@
+;;; The standard prelude: derived procedures written in Lisp on top of the built-in ones.
+;;; It is compiled into the interpreter and loaded into every library environment and,
+;;; unless the interpreter is started with --no-prelude, the top-level one. Setting
+;;; LISP_PRELUDE to a file name loads that file instead.
+
+;; Booleans and equivalence
+
+(define (not x)            (eqv? x #f))
+(define (null? obj)        (eqv? obj '()))
+(define (boolean? obj)     (if (eqv? obj #t) #t (eqv? obj #f)))
+
+(define (sum . lst)         (fold + 0 lst))
+(define (product . lst)     (fold * 1 lst))
+(define (and . lst)         (fold && #t lst))
+(define (or . lst)          (fold || #f lst))
+
+;; Numbers
+
+(define (>= a b)           (not (< a b)))
+(define (abs n)            (if (< n 0) (- 0 n) n))
+(define (square n)         (* n n))
+(define (even? num)        (= (mod num 2) 0))
+(define (odd? num)         (not (even? num)))
+
+(define (gcd a b)          (if (= b 0) (abs a) (gcd b (mod a b))))
+(define (lcm a b)          (if (= b 0) 0 (abs (/ (* a b) (gcd a b)))))
+
+(define (max first . rest) (fold (lambda (old new) (if (> old new) old new)) first rest))
+(define (min first . rest) (fold (lambda (old new) (if (< old new) old new)) first rest))
+
+(define (factorial x) (if (= x 1) 1 (* x (factorial (- x 1)))))
+
+;; Pairs and lists
+
+(define (caar x)           (car (car x)))
+(define (cadr x)           (car (cdr x)))
+(define (cdar x)           (cdr (car x)))
+(define (cddr x)           (cdr (cdr x)))
+(define (caddr x)          (car (cddr x)))
+(define (cdddr x)          (cdr (cddr x)))
+(define (cadddr x)         (car (cdddr x)))
+
+(define (first lst)        (car lst))
+(define (second lst)       (cadr lst))
+(define (third lst)        (caddr lst))
+
+(define (list-copy lst)    (fold-right cons '() lst))
+(define (list-set! lst k obj) (set-car! (list-tail lst k) obj))
+
+(define (find-tail pred lst)
+  (if (null? lst)
+      #f
+      (if (pred (car lst)) lst (find-tail pred (cdr lst)))))
+
+(define (find pred lst)
+  (define tail (find-tail pred lst))
+  (if (eqv? tail #f) #f (car tail)))
+
+(define (count pred lst)
+  (fold (lambda (x n) (if (pred x) (+ n 1) n)) 0 lst))
+
+(define (filter-map f lst)
+  (fold-right (lambda (x acc) (define y (f x)) (if (eqv? y #f) acc (cons y acc))) '() lst))
+
+;; Higher-order functions
+
+(define (id obj)           obj)
+(define (const obj)        (lambda args obj))
+(define (flip func)        (lambda (arg1 arg2) (func arg2 arg1)))
+(define (curry func arg1)  (lambda args (apply func (cons arg1 args))))
+(define (compose f g)      (lambda args (f (apply g args))))
+(define (complement pred)  (lambda args (not (apply pred args))))
+
+(define zero?              (curry = 0))
+(define positive?          (curry < 0))
+(define negative?          (curry > 0))
+
+(define (foldr func end lst)
+  (if (null? lst)
+      end
+      (func (car lst) (foldr func end (cdr lst)))))
+
+(define (foldl func accum lst)
+  (if (null? lst)
+      accum
+      (foldl func (func accum (car lst)) (cdr lst))))
@@ -0,0 +1,221 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use crate::env::Env;
+use crate::error::LispErr;
+use crate::error::LispErr::Runtime;
+use crate::lispval::LispVal;
+use crate::lispval::LispVal::{Number, Unspecified};
+use crate::port::output_port;
+use crate::primitive_functions::expect_args_between;
+use crate::printer::{is_circular, print_string, Mode};
+
+thread_local! {
+    /// Column the REPL and `pretty-print` try to keep their output within.
+    static WIDTH: Cell<usize> = const { Cell::new(80) };
+}
+
+pub fn pretty_width() -> usize {
+    WIDTH.with(|w| w.get())
+}
+
+/// A document in the style of Wadler's "A prettier printer": every `Line` in a `Group`
+/// becomes a space if the whole group fits on the rest of the line, and a newline
+/// followed by the current indentation otherwise.
+enum Doc {
+    Text(String),
+    Line,
+    /// Indents lines in the inner document by this much more than the enclosing one.
+    Nest(usize, Box<Doc>),
+    /// Indents lines in the inner document to the column it starts at.
+    Align(Box<Doc>),
+    Group(Box<Doc>),
+    Concat(Vec<Doc>),
+}
+
+fn text(s: &str) -> Doc {
+    Doc::Text(s.to_string())
+}
+
+fn group(items: Vec<Doc>) -> Doc {
+    Doc::Group(Box::new(Doc::Concat(items)))
+}
+
+fn align(items: Vec<Doc>) -> Doc {
+    Doc::Align(Box::new(Doc::Concat(items)))
+}
+
+/// `docs` separated by `Line`s.
+fn lines(docs: impl IntoIterator<Item=Doc>) -> Vec<Doc> {
+    let mut items = vec![];
+    for d in docs {
+        if !items.is_empty() {
+            items.push(Doc::Line);
+        }
+        items.push(d);
+    }
+    items
+}
+
+/// Number of arguments kept on the first line of a special form; the rest form a body
+/// indented by two columns.
+fn special_form_arguments(name: &str, items: &[LispVal]) -> Option<usize> {
+    match name {
+        "begin" => Some(0),
+        "receive" => Some(2),
+        "let" if matches!(items.get(1), Some(LispVal::Symbol(_))) => Some(2),
+        "define" | "lambda" | "let" | "let*" | "letrec" | "letrec*" | "let-values" | "let*-values" |
+        "when" | "unless" | "case" | "do" | "while" | "dotimes" | "dolist" | "parameterize" |
+        "define-record-type" | "define-library" | "syntax-rules" | "case-lambda" => Some(1),
+        _ => None,
+    }
+}
+
+fn doc(v: &LispVal) -> Doc {
+    match v {
+        LispVal::Pair(_) => list_doc(v),
+        LispVal::Quote(q) => Doc::Concat(vec![text("'"), doc(q)]),
+        _ => Doc::Text(print_string(v, Mode::Write)),
+    }
+}
+
+fn list_doc(v: &LispVal) -> Doc {
+    let Ok((items, tail)) = v.split_improper() else {
+        return Doc::Text(print_string(v, Mode::Write));
+    };
+    let mut docs: Vec<Doc> = items.iter().map(doc).collect();
+    if tail != LispVal::Nil {
+        docs.push(Doc::Concat(vec![text(". "), doc(&tail)]));
+    }
+    let special = match &items[0] {
+        LispVal::Symbol(s) => Some(special_form_arguments(&s.name(), &items)),
+        _ => None,
+    };
+    let mut docs = docs.into_iter();
+    let head = docs.next().unwrap();
+    match special {
+        // (define (f x)
+        //   body)
+        Some(Some(n)) => {
+            let mut body = vec![];
+            for (i, d) in docs.enumerate() {
+                body.push(if i < n { text(" ") } else { Doc::Line });
+                body.push(d);
+            }
+            align(vec![group(vec![text("("), head, Doc::Nest(2, Box::new(Doc::Concat(body))), text(")")])])
+        }
+        // (f a
+        //    b)
+        Some(None) if docs.len() > 0 => group(vec![text("("), head, text(" "), align(lines(docs)), text(")")]),
+        // (1
+        //  2)
+        _ => group(vec![text("("), align(lines(std::iter::once(head).chain(docs))), text(")")]),
+    }
+}
+
+/// Whether the flat layout of `items`, the top of the layout stack, reaches the next line
+/// break without going past `remaining` columns.
+fn fits(mut remaining: isize, mut items: Vec<(bool, &Doc)>) -> bool {
+    while let Some((flat, d)) = items.pop() {
+        if remaining < 0 {
+            return false;
+        }
+        match d {
+            Doc::Text(s) => remaining -= s.chars().count() as isize,
+            Doc::Line if flat => remaining -= 1,
+            Doc::Line => return true,
+            Doc::Nest(_, d) | Doc::Align(d) | Doc::Group(d) => items.push((flat, d)),
+            Doc::Concat(ds) => items.extend(ds.iter().rev().map(|d| (flat, d))),
+        }
+    }
+    remaining >= 0
+}
+
+fn layout(doc: &Doc, width: usize) -> String {
+    let mut out = String::new();
+    let mut column = 0;
+    let mut stack: Vec<(usize, bool, &Doc)> = vec![(0, false, doc)];
+    while let Some((indent, flat, d)) = stack.pop() {
+        match d {
+            Doc::Text(s) => {
+                out.push_str(s);
+                column += s.chars().count();
+            }
+            Doc::Line if flat => {
+                out.push(' ');
+                column += 1;
+            }
+            Doc::Line => {
+                out.push('\n');
+                out.push_str(&" ".repeat(indent));
+                column = indent;
+            }
+            Doc::Nest(i, d) => stack.push((indent + i, flat, d)),
+            Doc::Align(d) => stack.push((column, flat, d)),
+            Doc::Group(d) => {
+                let rest = stack.iter().map(|(_, flat, d)| (*flat, *d));
+                let flat = flat || fits(width as isize - column as isize, rest.chain([(true, &**d)]).collect());
+                stack.push((indent, flat, d));
+            }
+            Doc::Concat(ds) => stack.extend(ds.iter().rev().map(|d| (indent, flat, d))),
+        }
+    }
+    out
+}
+
+/// `v` as `write` prints it, broken over lines to fit in `width` columns where possible.
+/// Circular data is written on one line with datum labels.
+pub fn pretty_string(v: &LispVal, width: usize) -> String {
+    if is_circular(v) {
+        return print_string(v, Mode::Write);
+    }
+    layout(&doc(v), width)
+}
+
+/// `(pretty-print obj [port])`
+fn pretty_print(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args_between(a, 1, 2)?;
+    let port = output_port(a, 1)?;
+    port.write_str(&pretty_string(&a[0], pretty_width()))?;
+    port.write_str("\n")?;
+    Ok(Unspecified)
+}
+
+/// `(pretty-print-width)` returns the width; `(pretty-print-width n)` sets it.
+fn pretty_print_width(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args_between(a, 0, 1)?;
+    if let Some(n) = a.first() {
+        let width = usize::try_from(n.num()?).map_err(|_| Runtime(format!("Expected width but got {}", n)))?;
+        WIDTH.with(|w| w.set(width));
+        return Ok(Unspecified);
+    }
+    Ok(Number(pretty_width() as i64))
+}
+
+pub fn define_pretty_functions(e: &mut Env) {
+    e.define_primitive("pretty-print", pretty_print).unwrap();
+    e.define_primitive("pretty-print-width", pretty_print_width).unwrap();
+}
+
+#[test]
+fn pretty_print_test() {
+    use crate::port::Port;
+    let read = |s: &str| Port::string_input(s).read_datum().unwrap();
+    let code = read("(define (f x) (cond ((= x 0) 'zero) ((= x 1) 'one) (else (let ((y (- x 1))) (f y)))))");
+    assert_eq!(pretty_string(&code, 100), code.to_string());
+    assert_eq!(pretty_string(&code, 40), "\
+(define (f x)
+  (cond ((= x 0) 'zero)
+        ((= x 1) 'one)
+        (else (let ((y (- x 1)))
+                (f y)))))");
+    assert_eq!(pretty_string(&read("(lambda (a b) (display a) (display b))"), 20), "\
+(lambda (a b)
+  (display a)
+  (display b))");
+    assert_eq!(pretty_string(&read("((1 2 3) (4 5 6) . 7)"), 10), "\
+((1 2 3)
+ (4 5 6)
+ . 7)");
+    assert_eq!(pretty_string(&read("#0=(1 . #0#)"), 2), "#0=(1 . #0#)");
+}
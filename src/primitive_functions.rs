@@ -1,65 +1,57 @@
 use std::cell::RefCell;
-use std::fs::File;
-use std::io::Read;
-use std::ops::Deref;
-use std::path::Path;
 use std::rc::Rc;
-use std::string::String;
 
 use crate::env::Env;
 use crate::error::LispErr;
 use crate::error::LispErr::Runtime;
 use crate::evaluation::eval;
-use crate::lispval::LispVal;
-use crate::lispval::LispVal::{Boolean, DottedList, List, Number, PrimitiveFunc};
-use crate::parser::parse_vector;
+use crate::hash_table::define_hash_table_functions;
+use crate::list_functions::define_list_functions;
+use crate::sort::define_sort_functions;
+use crate::symbol::define_symbol_functions;
+use crate::port::define_port_functions;
+use crate::filesystem::define_filesystem_functions;
+use crate::pretty::define_pretty_functions;
+use crate::format::define_format_functions;
+use crate::parameter::define_parameter_functions;
+use crate::library::{define_library_functions, eval_file, resolve};
+use crate::promise::{define_promise_functions, define_stream_library};
+use crate::prelude::standard_env;
+use crate::lispval::{LispVal, Pair};
+use crate::lispval::LispVal::{Boolean, Number};
 
+/// `(load path)` evaluates the file at `path`, which is relative to the directory of the
+/// file being loaded if there is one.
 pub fn load(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
-    if a.len() != 1 {
-        return Err(Runtime("Expected one argument".to_string()));
-    }
-
-    let mut file = match File::open(Path::new(&a[0].str()?)) {
-        Err(_) => return Err(Runtime("Error reading file".to_string())),
-        Ok(f) => f,
-    };
-    let mut s = String::new();
-    _ = file.read_to_string(&mut s);
-    let expressions = match parse_vector(&s) {
-        Ok((_, v)) => v,
-        Err(err) => return Err(Runtime(err.to_string())),
-    };
+    expect_args(a, 1)?;
+    eval_file(&resolve(&a[0].str()?), env)
+}
 
-    //expressions.iter().for_each(|e| println!("{}", e));
+pub fn expect_args(a: &[LispVal], n: usize) -> Result<(), LispErr> {
+    if a.len() != n {
+        return Err(Runtime(format!("Expected {} arguments but got {}", n, a.len())));
+    }
+    Ok(())
+}
 
-    match expressions.len() {
-        0 => return Ok(LispVal::List(vec![])),
-        1 => eval(&expressions[0], &env),
-        _ => expressions[1..].iter().try_fold(eval(&expressions[0], &env)?, |_, v| eval(v, &env))
+pub fn expect_args_between(a: &[LispVal], min: usize, max: usize) -> Result<(), LispErr> {
+    if a.len() < min || a.len() > max {
+        return Err(Runtime(format!("Expected {} to {} arguments but got {}", min, max, a.len())));
     }
+    Ok(())
 }
+
 fn cons(p: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
     if p.len() != 2 {
         return Err(Runtime("Expected two arguments".to_string()));
     }
-    let a = &p[0];
-    let b = &p[1];
-    match b {
-        LispVal::List(xs) =>
-            match xs.len() {
-                0 => Ok(List(vec![a.clone()])),
-                _ => {
-                    let mut list = xs.clone();
-                    list.insert(0, a.clone());
-                    Ok(List(list))
-                },
-            },
-        LispVal::DottedList(xs, r) => {
-            let mut list = xs.clone();
-            list.insert(0, a.clone());
-            Ok(DottedList(list, r.clone()))
-        },
-        _ => Ok(DottedList(vec![a.clone()], Box::new(b.clone())))
+    Ok(LispVal::cons(p[0].clone(), p[1].clone()))
+}
+
+fn pair(v: &LispVal) -> Result<&Rc<Pair>, LispErr> {
+    match v {
+        LispVal::Pair(p) => Ok(p),
+        _ => Err(Runtime(format!("Expected pair but got {}", v)))
     }
 }
 
@@ -67,83 +59,280 @@ fn car(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
     if a.len() != 1 {
         return Err(Runtime("Expected one argument".to_string()));
     }
-    match &a[0] {
-        LispVal::List(v) => Ok(v[0].clone()),
-        LispVal::DottedList(v, _) => Ok(v[0].clone()),
-        _ => Err(Runtime("Expected list".to_string()))
-    }
+    Ok(pair(&a[0])?.car())
 }
 
 fn cdr(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
-    if a.len() > 1 {
+    if a.len() != 1 {
         return Err(Runtime("Expected one argument".to_string()));
     }
-    match &a[0] {
-        LispVal::List(v) => {atleast(v, 1)?; Ok(LispVal::List(v[1..].to_vec()))},
-        LispVal::DottedList(v, r) => {
-            if v.len() == 1 {
-                Ok(r.deref().clone())
-            } else {
-                Ok(DottedList(v[1..].to_vec(), r.clone()))
-            }
-        },
-        _ => Err(Runtime("Expected list".to_string()))
+    Ok(pair(&a[0])?.cdr())
+}
+
+fn set_car(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 2)?;
+    *pair(&a[0])?.car.borrow_mut() = a[1].clone();
+    Ok(a[1].clone())
+}
+
+fn set_cdr(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 2)?;
+    *pair(&a[0])?.cdr.borrow_mut() = a[1].clone();
+    Ok(a[1].clone())
+}
+
+pub fn eqv(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 2)?;
+    Ok(Boolean(crate::lispval::eqv(&a[0], &a[1])))
+}
+
+pub fn equal(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 2)?;
+    Ok(Boolean(crate::lispval::equal(&a[0], &a[1])))
+}
+
+fn string_eq(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 2)?;
+    Ok(Boolean(a[0].str()? == a[1].str()?))
+}
+
+fn apply(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 2)?;
+    crate::evaluation::call_function(&a[0], &a[1].to_vec()?, env)
+}
+
+/// `(error message irritant ...)`
+fn error(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    let Some((message, irritants)) = a.split_first() else {
+        return Err(Runtime("error: expected message".to_string()));
+    };
+    let mut text = crate::printer::print_string(message, crate::printer::Mode::Display);
+    for v in irritants {
+        text.push(' ');
+        text.push_str(&v.to_string());
+    }
+    Err(Runtime(text))
+}
+
+/// `(call-with-values producer consumer)`
+fn call_with_values(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 2)?;
+    let v = crate::evaluation::call_function(&a[0], &[], env)?;
+    crate::evaluation::call_with_values(&a[1], &v, env)
+}
+
+/// `(truncate/ n d)` and `(floor/ n d)` return the quotient and the remainder.
+fn divide_with_remainder(a: &[LispVal], floor: bool) -> Result<LispVal, LispErr> {
+    expect_args(a, 2)?;
+    let (n, d) = (a[0].num()?, a[1].num()?);
+    if d == 0 {
+        return Err(Runtime("Division by zero".to_string()));
     }
+    let (Some(q), Some(r)) = (n.checked_div(d), n.checked_rem(d)) else {
+        return Err(Runtime("Integer overflow".to_string()));
+    };
+    // Truncation rounds towards zero; floor rounds down, giving the remainder the sign of d.
+    let (q, r) = if floor && r != 0 && (r < 0) != (d < 0) { (q - 1, r + d) } else { (q, r) };
+    Ok(LispVal::values(vec![Number(q), Number(r)]))
 }
 
-fn atleast(v: &[LispVal], i: usize) -> Result<(), LispErr> {
-    if v.len() < i {
-        return Err(Runtime(format!("Expected at least {}", i).to_string()));
+fn environment(v: &LispVal) -> Result<Rc<RefCell<Env>>, LispErr> {
+    match v {
+        LispVal::Environment(e) => Ok(e.clone()),
+        _ => Err(Runtime(format!("Expected environment but got {}", v))),
     }
-    Ok(())
 }
 
-fn eqv(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
-    Ok(Boolean(a[0].eq(&a[1])))
+/// `(eval expr [environment])`, evaluating in the interaction environment by default.
+fn eval_procedure(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args_between(a, 1, 2)?;
+    let target = match a.get(1) {
+        Some(e) => environment(e)?,
+        None => Env::root(env),
+    };
+    eval(&a[0], &target)
 }
-fn equal(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
-    Ok(Boolean(a[0].to_string().eq(&a[1].to_string()))) // todo!
+
+fn report_version(a: &[LispVal]) -> Result<(), LispErr> {
+    expect_args(a, 1)?;
+    match a[0].num()? {
+        5 | 7 => Ok(()),
+        v => Err(Runtime(format!("Unsupported report version {}", v))),
+    }
 }
 
-fn apply(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
-    let f = &a[0];
-    if let List(args) = &a[1] {
-        crate::evaluation::call_function(f, args, env)
-    } else {
-        Err(Runtime("Expected list of arguments".to_string()))
+/// `(scheme-report-environment version)` is a fresh environment with the builtins and the
+/// prelude, so definitions made in it never leak into the caller's.
+fn scheme_report_environment(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    report_version(a)?;
+    Ok(LispVal::Environment(standard_env()?))
+}
+
+/// `(null-environment version)` binds nothing, leaving only the special forms.
+fn null_environment(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    report_version(a)?;
+    Ok(LispVal::Environment(Rc::new(RefCell::new(Env::new()))))
+}
+
+/// Folds `op` over the arguments, so `(- x)` is `0 - x`, failing on integer overflow.
+fn fold_numbers(a: &[LispVal], unit: i64, op: fn(i64, i64) -> Option<i64>) -> Result<LispVal, LispErr> {
+    let overflow = || Runtime("Integer overflow".to_string());
+    match a {
+        [] => Ok(Number(unit)),
+        [x] => op(unit, x.num()?).map(Number).ok_or_else(overflow),
+        [x, rest @ ..] => rest.iter()
+            .try_fold(x.num()?, |acc, v| op(acc, v.num()?).ok_or_else(overflow))
+            .map(Number),
+    }
+}
+
+/// `(/ n)` is `1/n`, and `(/ n d ...)` divides `n` by each `d` in turn.
+fn divide(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    let (mut result, divisors) = match a {
+        [] => return Ok(Number(1)),
+        [_] => (1, a),
+        [x, rest @ ..] => (x.num()?, rest),
+    };
+    for v in divisors {
+        let d = v.num()?;
+        if d == 0 {
+            return Err(Runtime("Division by zero".to_string()));
+        }
+        result = result.checked_div(d).ok_or_else(|| Runtime("Integer overflow".to_string()))?;
     }
+    Ok(Number(result))
 }
+
 pub fn create_eden_env() -> Rc<RefCell<Env>> {
     let env: Rc<RefCell<Env>> = Rc::from(RefCell::new(Env::new()));
     {
         let mut e = env.borrow_mut();
-        e.define("+", PrimitiveFunc(|a, _| Ok(Number(a[0].num()? + a[1].num()?)))).unwrap();
-        e.define("-", PrimitiveFunc(|a, _| Ok(Number(a[0].num()? - a[1].num()?)))).unwrap();
-        e.define("*", PrimitiveFunc(|a, _| Ok(Number(a[0].num()? * a[1].num()?)))).unwrap();
-        e.define("/", PrimitiveFunc(|a, _| Ok(Number(a[0].num()? / a[1].num()?)))).unwrap();
-        e.define("mod", PrimitiveFunc(|a, _| Ok(Number(a[0].num()? % a[1].num()?)))).unwrap();
-        e.define("quotent", PrimitiveFunc(|a, _| Ok(Number(a[0].num()? / a[1].num()?)))).unwrap();
-        e.define("remainder", PrimitiveFunc(|a, _| Ok(Number(a[0].num()? + a[1].num()?)))).unwrap();
-        e.define("=", PrimitiveFunc(|a, _| Ok(Boolean(a[0].num()? == a[1].num()?)))).unwrap();
-        e.define(">", PrimitiveFunc(|a, _| Ok(Boolean(a[0].num()? > a[1].num()?)))).unwrap();
-        e.define("<", PrimitiveFunc(|a, _| Ok(Boolean(a[0].num()? < a[1].num()?)))).unwrap();
-        e.define("=>", PrimitiveFunc(|a, _| Ok(Boolean(a[0].num()? >= a[1].num()?)))).unwrap();
-        e.define("<=", PrimitiveFunc(|a, _| Ok(Boolean(a[0].num()? <= a[1].num()?)))).unwrap();
-        e.define("&&", PrimitiveFunc(|a, _| Ok(Boolean(a[0].bool()? && a[1].bool()?)))).unwrap();
-        e.define("||", PrimitiveFunc(|a, _| Ok(Boolean(a[0].bool()? || a[1].bool()?)))).unwrap();
-        e.define("/=", PrimitiveFunc(|a, _| Ok(Boolean(a[0].bool()? != a[1].bool()?)))).unwrap();
-        e.define("string=?", PrimitiveFunc(|a, _| Ok(Boolean(a[0].str()? == (a[1].str()?))))).unwrap();
-        e.define("string<?", PrimitiveFunc(|a, _| Ok(Boolean(a[0].str()? < a[1].str()?)))).unwrap();
-        e.define("string>?", PrimitiveFunc(|a, _| Ok(Boolean(a[0].str()? > a[1].str()?)))).unwrap();
-        e.define("string<=?", PrimitiveFunc(|a, _| Ok(Boolean(a[0].str()? <= a[1].str()?)))).unwrap();
-        e.define("string>=?", PrimitiveFunc(|a, _| Ok(Boolean(a[0].str()? >= a[1].str()?)))).unwrap();
-        e.define("car", PrimitiveFunc(car)).unwrap();
-        e.define("cdr", PrimitiveFunc(cdr)).unwrap();
-        e.define("cons", PrimitiveFunc(cons)).unwrap();
-        e.define("eqv?", PrimitiveFunc(eqv)).unwrap();
-        e.define("equal?", PrimitiveFunc(equal)).unwrap();
-        e.define("apply", PrimitiveFunc(apply)).unwrap();
-        e.define("load", PrimitiveFunc(load)).unwrap();
+        e.define_primitive("+", |a, _| fold_numbers(a, 0, i64::checked_add)).unwrap();
+        e.define_primitive("-", |a, _| fold_numbers(a, 0, i64::checked_sub)).unwrap();
+        e.define_primitive("*", |a, _| fold_numbers(a, 1, i64::checked_mul)).unwrap();
+        e.define_primitive("/", divide).unwrap();
+        e.define_primitive("mod", |a, _| Ok(Number(a[0].num()? % a[1].num()?))).unwrap();
+        e.define_primitive("quotent", |a, _| Ok(Number(a[0].num()? / a[1].num()?))).unwrap();
+        e.define_primitive("remainder", |a, _| Ok(Number(a[0].num()? + a[1].num()?))).unwrap();
+        e.define_primitive("=", |a, _| Ok(Boolean(a[0].num()? == a[1].num()?))).unwrap();
+        e.define_primitive(">", |a, _| Ok(Boolean(a[0].num()? > a[1].num()?))).unwrap();
+        e.define_primitive("<", |a, _| Ok(Boolean(a[0].num()? < a[1].num()?))).unwrap();
+        e.define_primitive("=>", |a, _| Ok(Boolean(a[0].num()? >= a[1].num()?))).unwrap();
+        e.define_primitive("<=", |a, _| Ok(Boolean(a[0].num()? <= a[1].num()?))).unwrap();
+        e.define_primitive("&&", |a, _| Ok(Boolean(a[0].bool()? && a[1].bool()?))).unwrap();
+        e.define_primitive("||", |a, _| Ok(Boolean(a[0].bool()? || a[1].bool()?))).unwrap();
+        e.define_primitive("/=", |a, _| Ok(Boolean(a[0].bool()? != a[1].bool()?))).unwrap();
+        e.define_primitive("string=?", string_eq).unwrap();
+        e.define_primitive("string<?", |a, _| Ok(Boolean(a[0].str()? < a[1].str()?))).unwrap();
+        e.define_primitive("string>?", |a, _| Ok(Boolean(a[0].str()? > a[1].str()?))).unwrap();
+        e.define_primitive("string<=?", |a, _| Ok(Boolean(a[0].str()? <= a[1].str()?))).unwrap();
+        e.define_primitive("string>=?", |a, _| Ok(Boolean(a[0].str()? >= a[1].str()?))).unwrap();
+        e.define_primitive("car", car).unwrap();
+        e.define_primitive("cdr", cdr).unwrap();
+        e.define_primitive("cons", cons).unwrap();
+        e.define_primitive("set-car!", set_car).unwrap();
+        e.define_primitive("set-cdr!", set_cdr).unwrap();
+        e.define_primitive("pair?", |a, _| { expect_args(a, 1)?; Ok(Boolean(matches!(a[0], LispVal::Pair(_)))) }).unwrap();
+        e.define_primitive("eq?", eqv).unwrap();
+        e.define_primitive("eqv?", eqv).unwrap();
+        e.define_primitive("equal?", equal).unwrap();
+        e.define_primitive("apply", apply).unwrap();
+        e.define_primitive("load", load).unwrap();
+        e.define_primitive("eval", eval_procedure).unwrap();
+        e.define_primitive("error", error).unwrap();
+        e.define_primitive("values", |a, _| Ok(LispVal::values(a.to_vec()))).unwrap();
+        e.define_primitive("call-with-values", call_with_values).unwrap();
+        e.define_primitive("truncate/", |a, _| divide_with_remainder(a, false)).unwrap();
+        e.define_primitive("floor/", |a, _| divide_with_remainder(a, true)).unwrap();
+        e.define_primitive("interaction-environment", |a, env| { expect_args(a, 0)?; Ok(LispVal::Environment(Env::root(env))) }).unwrap();
+        e.define_primitive("scheme-report-environment", scheme_report_environment).unwrap();
+        e.define_primitive("null-environment", null_environment).unwrap();
+        e.define_primitive("environment?", |a, _| { expect_args(a, 1)?; Ok(Boolean(matches!(a[0], LispVal::Environment(_)))) }).unwrap();
+        define_hash_table_functions(&mut e);
+        define_list_functions(&mut e);
+        define_sort_functions(&mut e);
+        define_symbol_functions(&mut e);
+        define_port_functions(&mut e);
+        define_filesystem_functions(&mut e);
+        define_pretty_functions(&mut e);
+        define_format_functions(&mut e);
+        define_promise_functions(&mut e);
+        define_parameter_functions(&mut e);
+        define_library_functions(&mut e);
     }
+    define_stream_library(&env);
     env
 }
+
+#[test]
+fn eval_environment_test() {
+    use crate::evaluation::eval_str;
+    let env = &create_eden_env();
+    let run = |s: &str| eval_str(s, env).unwrap().to_string();
+    assert_eq!(run("(eval '(+ 1 2) (interaction-environment))"), "3");
+    run("(define x 1)");
+    assert_eq!(run("((lambda (x) (eval 'x)) 2)"), "1");
+    run("(define sandbox (scheme-report-environment 7))");
+    run("(eval '(define x 10) sandbox)");
+    assert_eq!(run("(eval 'x sandbox)"), "10");
+    assert_eq!(run("x"), "1");
+    assert_eq!(run("(eval (read (open-input-string \"(* x 3)\")) sandbox)"), "30");
+    assert_eq!(run("(eval '(list (square 3) (abs -2) (cadr '(1 2))) sandbox)"), "(9 2 2)");
+    run("(define empty (null-environment 7))");
+    assert_eq!(run("(eval '(if #t 1 2) empty)"), "1");
+    assert!(eval_str("(eval '(+ 1 2) empty)", env).is_err());
+    assert_eq!(run("(environment? empty)"), "#t");
+    assert!(eval_str("(null-environment 6)", env).is_err());
+}
+
+#[test]
+fn values_test() {
+    use crate::evaluation::eval_str;
+    let env = &create_eden_env();
+    let run = |s: &str| eval_str(s, env).unwrap().to_string();
+    assert_eq!(run("(call-with-values (lambda () (values 1 2)) +)"), "3");
+    assert_eq!(run("(call-with-values (lambda () 5) list)"), "(5)");
+    assert_eq!(run("(call-with-values values list)"), "()");
+    assert_eq!(run("(receive (q r) (truncate/ -7 2) (list q r))"), "(-3 -1)");
+    assert_eq!(run("(receive (q . rest) (floor/ -7 2) (list q rest))"), "(-4 (1))");
+    assert_eq!(run("(receive all (floor/ 7 -2) all)"), "(-4 -1)");
+    assert_eq!(run("(let-values (((a b) (values 1 2)) ((c) (values 3))) (list a b c))"), "(1 2 3)");
+    assert_eq!(run("(let*-values (((a) (values 1)) ((b) (values (+ a 1)))) (list a b))"), "(1 2)");
+    run("(define-values (x y . z) (values 1 2 3 4))");
+    assert_eq!(run("(list x y z)"), "(1 2 (3 4))");
+    assert!(eval_str("(receive (a b) (values 1) a)", env).is_err());
+    assert!(eval_str("(receive (a) (values 1 2) a)", env).is_err());
+    assert!(eval_str("(define w (values 1 2))", env).is_err());
+    assert!(eval_str("(define w (values))", env).is_err());
+    assert!(eval_str("(set! x (values 1 2))", env).is_err());
+    assert_eq!(run("x"), "1");
+    run("(set! x (values 5))");
+    assert_eq!(run("x"), "5");
+}
+
+#[test]
+fn string_eq_test() {
+    use crate::evaluation::eval_str;
+    let env = &create_eden_env();
+    let run = |s: &str| eval_str(s, env).unwrap().to_string();
+    assert_eq!(run("(string=? \"ab\" \"ab\")"), "#t");
+    assert!(eval_str("(string=? \"a\")", env).is_err());
+    assert!(eval_str("(string=? \"a\" \"a\" \"a\")", env).is_err());
+}
+
+#[test]
+fn arithmetic_test() {
+    use crate::evaluation::eval_str;
+    let env = &create_eden_env();
+    let run = |s: &str| eval_str(s, env).unwrap().to_string();
+    assert_eq!(run("(list (/ 100 5 2) (/ 2) (/ 1) (/))"), "(10 0 1 1)");
+    assert!(eval_str("(/ 0)", env).is_err());
+    assert!(eval_str("(/ 6 3 0)", env).is_err());
+    assert!(eval_str("(/ 1 \"0\")", env).is_err());
+    assert!(eval_str("(/ -9223372036854775808 -1)", env).is_err());
+    assert_eq!(run("(list (+ 1 2 3) (- 5) (- 10 1 2) (* 2 3 4) (+) (*))"), "(6 -5 7 24 0 1)");
+    assert!(eval_str("(* 9223372036854775807 2)", env).is_err());
+    assert!(eval_str("(+ 9223372036854775807 1)", env).is_err());
+    assert!(eval_str("(- -9223372036854775808)", env).is_err());
+    assert!(eval_str("(- -9223372036854775808 1)", env).is_err());
+}
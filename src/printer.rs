@@ -0,0 +1,408 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Write};
+use std::rc::Rc;
+
+use crate::lispval::{char_name, LispVal, Pair};
+use crate::record::Record;
+use crate::parser::parse_expr;
+use crate::symbol::Symbol;
+
+/// How a value is printed. The write modes produce text that `read` turns back into an
+/// equal value and differ only in which pairs get datum labels: `Write` labels the ones
+/// that are part of a cycle, `WriteShared` every pair reached more than once and
+/// `WriteSimple` none, so it never terminates on circular data. `Display` is meant for
+/// people and prints strings and characters raw.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    Write,
+    WriteShared,
+    WriteSimple,
+    Display,
+}
+
+pub fn print_string(v: &LispVal, mode: Mode) -> String {
+    let mut s = String::new();
+    print(v, mode, &mut s).unwrap();
+    s
+}
+
+pub fn print(v: &LispVal, mode: Mode, out: &mut impl Write) -> fmt::Result {
+    let mut labels = HashMap::new();
+    match mode {
+        Mode::Write | Mode::Display =>
+            find_cycles(v, &mut HashSet::new(), &mut HashSet::new(), &mut HashSet::new(), &mut labels),
+        Mode::WriteShared => find_shared(v, &mut HashSet::new(), &mut HashSet::new(), &mut labels),
+        Mode::WriteSimple => (),
+    }
+    Printer { mode, labels, next_label: 0, records: HashSet::new() }.print(v, out)
+}
+
+type PairPtr = *const Pair;
+
+/// Whether some pair in `v` can be reached from itself.
+pub fn is_circular(v: &LispVal) -> bool {
+    let mut labels = HashMap::new();
+    find_cycles(v, &mut HashSet::new(), &mut HashSet::new(), &mut HashSet::new(), &mut labels);
+    !labels.is_empty()
+}
+
+/// The values a record or a multiple-values object holds, which the passes below look
+/// through as they do through pairs.
+fn members(v: &LispVal) -> Vec<LispVal> {
+    match v {
+        LispVal::Record(r) => r.values.borrow().clone(),
+        LispVal::Values(vs) => vs.clone(),
+        _ => vec![],
+    }
+}
+
+/// Marks the pairs reachable from themselves. `active` holds the pairs on the path from
+/// the root, so meeting one of them again closes a cycle; `done` pairs were fully explored.
+/// `records` are the records already looked into.
+fn find_cycles(v: &LispVal, active: &mut HashSet<PairPtr>, done: &mut HashSet<PairPtr>,
+               records: &mut HashSet<*const Record>, labels: &mut HashMap<PairPtr, Option<usize>>) {
+    let mut chain = vec![];
+    let mut v = v.clone();
+    loop {
+        match v {
+            LispVal::Pair(p) => {
+                let ptr = Rc::as_ptr(&p);
+                if active.contains(&ptr) {
+                    labels.insert(ptr, None);
+                }
+                if active.contains(&ptr) || done.contains(&ptr) {
+                    break;
+                }
+                active.insert(ptr);
+                chain.push(ptr);
+                find_cycles(&p.car(), active, done, records, labels);
+                v = p.cdr();
+            }
+            LispVal::Quote(q) => v = *q,
+            LispVal::Record(ref r) if !records.insert(Rc::as_ptr(r)) => break,
+            LispVal::Record(_) | LispVal::Values(_) => {
+                for m in members(&v) {
+                    find_cycles(&m, active, done, records, labels);
+                }
+                break;
+            }
+            _ => break,
+        }
+    }
+    for ptr in chain {
+        active.remove(&ptr);
+        done.insert(ptr);
+    }
+}
+
+/// Marks the pairs reachable more than once.
+fn find_shared(v: &LispVal, seen: &mut HashSet<PairPtr>, records: &mut HashSet<*const Record>,
+               labels: &mut HashMap<PairPtr, Option<usize>>) {
+    let mut v = v.clone();
+    loop {
+        match v {
+            LispVal::Pair(p) => {
+                let ptr = Rc::as_ptr(&p);
+                if !seen.insert(ptr) {
+                    labels.insert(ptr, None);
+                    break;
+                }
+                find_shared(&p.car(), seen, records, labels);
+                v = p.cdr();
+            }
+            LispVal::Quote(q) => v = *q,
+            LispVal::Record(ref r) if !records.insert(Rc::as_ptr(r)) => break,
+            LispVal::Record(_) | LispVal::Values(_) => {
+                for m in members(&v) {
+                    find_shared(&m, seen, records, labels);
+                }
+                break;
+            }
+            _ => break,
+        }
+    }
+}
+
+struct Printer {
+    mode: Mode,
+    /// Pairs that need a datum label, with the number assigned once the first one is printed.
+    labels: HashMap<PairPtr, Option<usize>>,
+    next_label: usize,
+    /// Records being printed, which would recurse forever if one contains itself.
+    records: HashSet<*const Record>,
+}
+
+impl Printer {
+    fn print(&mut self, v: &LispVal, out: &mut impl Write) -> fmt::Result {
+        let write = self.mode != Mode::Display;
+        match v {
+            LispVal::Symbol(s) if write => write_symbol(&s.name(), out),
+            LispVal::Symbol(s) => write!(out, "{}", s),
+            LispVal::Number(n) => write!(out, "{}", n),
+            LispVal::LispString(s) if write => write_escaped(s, out),
+            LispVal::LispString(s) => out.write_str(s),
+            LispVal::Char(c) if write => write_char(*c, out),
+            LispVal::Char(c) => out.write_char(*c),
+            LispVal::Boolean(true) => out.write_str("#t"),
+            LispVal::Boolean(false) => out.write_str("#f"),
+            LispVal::Nil => out.write_str("()"),
+            LispVal::Pair(p) => self.print_pair(p, out),
+            LispVal::Quote(q) => {
+                out.write_char('\'')?;
+                self.print(q, out)
+            }
+            LispVal::Func { name, .. } | LispVal::CaseLambda { name, .. } => write_procedure(*name, out),
+            LispVal::PrimitiveFunc { name, .. } => write_procedure(*name, out),
+            LispVal::NativeFunc(f) => write_procedure(Some(f.name), out),
+            LispVal::HashTable(t) => write!(out, "#<hash-table {}>", t.borrow().len()),
+            LispVal::Port(p) => write!(out, "#<port {}>", p.name),
+            LispVal::Environment(_) => out.write_str("#<environment>"),
+            LispVal::RecordType(t) => write!(out, "#<record-type {}>", t.display_name()),
+            LispVal::Record(r) => self.print_record(r, out),
+            LispVal::Promise(_) => out.write_str("#<promise>"),
+            LispVal::Parameter(_) => out.write_str("#<parameter>"),
+            LispVal::Values(vs) => {
+                for (i, v) in vs.iter().enumerate() {
+                    if i > 0 {
+                        out.write_char(' ')?;
+                    }
+                    self.print(v, out)?;
+                }
+                Ok(())
+            }
+            LispVal::Eof => out.write_str("#<eof>"),
+            LispVal::Unspecified => out.write_str("#<unspecified>"),
+        }
+    }
+
+    /// `#<point x: 1 y: 2>`, or just `#<point>` for a record already being printed.
+    fn print_record(&mut self, r: &Rc<Record>, out: &mut impl Write) -> fmt::Result {
+        let name = r.record_type.display_name();
+        if !self.records.insert(Rc::as_ptr(r)) {
+            return write!(out, "#<{}>", name);
+        }
+        write!(out, "#<{}", name)?;
+        for (field, v) in r.record_type.fields.iter().zip(r.values.borrow().iter()) {
+            write!(out, " {}: ", field)?;
+            self.print(v, out)?;
+        }
+        self.records.remove(&Rc::as_ptr(r));
+        out.write_char('>')
+    }
+
+    fn print_pair(&mut self, p: &Rc<Pair>, out: &mut impl Write) -> fmt::Result {
+        match self.labels.get(&Rc::as_ptr(p)) {
+            Some(Some(n)) => return write!(out, "#{}#", n),
+            Some(None) => {
+                let n = self.next_label;
+                self.next_label += 1;
+                self.labels.insert(Rc::as_ptr(p), Some(n));
+                write!(out, "#{}=", n)?;
+            }
+            None => (),
+        }
+        out.write_char('(')?;
+        self.print(&p.car(), out)?;
+        let mut tail = p.cdr();
+        // A labelled pair in the tail has to be printed as a datum of its own after a dot.
+        while let LispVal::Pair(p) = &tail {
+            if self.labels.contains_key(&Rc::as_ptr(p)) {
+                break;
+            }
+            out.write_char(' ')?;
+            self.print(&p.car(), out)?;
+            let next = p.cdr();
+            tail = next;
+        }
+        if tail != LispVal::Nil {
+            out.write_str(" . ")?;
+            self.print(&tail, out)?;
+        }
+        out.write_char(')')
+    }
+}
+
+fn write_procedure(name: Option<Symbol>, out: &mut impl Write) -> fmt::Result {
+    match name {
+        Some(name) => write!(out, "#<procedure {}>", name),
+        None => out.write_str("#<procedure>"),
+    }
+}
+
+/// Escape used for `c` inside a string or `|symbol|` literal, if it needs one.
+fn escape(c: char, delimiter: char) -> Option<String> {
+    match c {
+        '\\' => Some("\\\\".to_string()),
+        '\n' => Some("\\n".to_string()),
+        '\t' => Some("\\t".to_string()),
+        '\r' => Some("\\r".to_string()),
+        '\x07' => Some("\\a".to_string()),
+        '\x08' => Some("\\b".to_string()),
+        _ if c == delimiter => Some(format!("\\{}", c)),
+        _ if c.is_control() => Some(format!("\\x{:x};", c as u32)),
+        _ => None,
+    }
+}
+
+fn write_delimited(s: &str, delimiter: char, out: &mut impl Write) -> fmt::Result {
+    out.write_char(delimiter)?;
+    for c in s.chars() {
+        match escape(c, delimiter) {
+            Some(e) => out.write_str(&e)?,
+            None => out.write_char(c)?,
+        }
+    }
+    out.write_char(delimiter)
+}
+
+fn write_escaped(s: &str, out: &mut impl Write) -> fmt::Result {
+    write_delimited(s, '"', out)
+}
+
+/// Symbols whose names would read back as something else are written as `|name|`.
+fn write_symbol(name: &str, out: &mut impl Write) -> fmt::Result {
+    // A letter followed by letters, digits and punctuation can only read as a symbol.
+    let mut chars = name.chars();
+    if chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || "!$%&*+-/:<=>?@^_~".contains(c)) {
+        return out.write_str(name);
+    }
+    match parse_expr(name) {
+        Ok(("", LispVal::Symbol(s))) if &*s.name() == name => out.write_str(name),
+        _ => write_delimited(name, '|', out),
+    }
+}
+
+fn write_char(c: char, out: &mut impl Write) -> fmt::Result {
+    match char_name(c) {
+        Some(name) => write!(out, "#\\{}", name),
+        None if c.is_control() || c.is_whitespace() => write!(out, "#\\x{:x}", c as u32),
+        None => write!(out, "#\\{}", c),
+    }
+}
+
+/// Deterministic xorshift generator, so a failing case can be reproduced from its seed.
+#[cfg(test)]
+struct Rng(u64);
+
+#[cfg(test)]
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn char(&mut self) -> char {
+        const SPECIAL: &[char] = &['"', '\\', '|', '\n', '\t', '\r', '\0', '\x07', '\x1b', ' ', '(', ')', ';', '#', 'λ', 'é', '→'];
+        match self.below(3) {
+            0 => SPECIAL[self.below(SPECIAL.len() as u64) as usize],
+            1 => char::from_u32(self.below(0x3000) as u32).unwrap_or('?'),
+            _ => (b'a' + self.below(26) as u8) as char,
+        }
+    }
+
+    fn string(&mut self) -> String {
+        (0..self.below(8)).map(|_| self.char()).collect()
+    }
+
+    fn datum(&mut self, depth: u32) -> LispVal {
+        let leaf = depth == 0 || self.below(3) > 0;
+        match self.below(if leaf { 6 } else { 9 }) {
+            0 => LispVal::Number(match self.below(4) {
+                0 => i64::MIN,
+                1 => i64::MAX,
+                _ => self.next() as i64 >> self.below(64),
+            }),
+            1 => LispVal::LispString(self.string()),
+            2 => LispVal::Char(self.char()),
+            3 => LispVal::Boolean(self.below(2) == 0),
+            4 => LispVal::Nil,
+            5 => {
+                const NAMES: &[&str] = &["foo", "+", "-", "...", "set-car!", "a->b", "<=?", "x1", "#t", "1+", "", "two words", "|"];
+                match self.below(2) {
+                    0 => LispVal::symbol(NAMES[self.below(NAMES.len() as u64) as usize]),
+                    _ => LispVal::symbol(&self.string()),
+                }
+            }
+            6 => LispVal::Quote(Box::new(self.datum(depth - 1))),
+            7 => LispVal::list((0..self.below(5)).map(|_| self.datum(depth - 1)).collect()),
+            _ => {
+                let items = (0..=self.below(4)).map(|_| self.datum(depth - 1)).collect();
+                let tail = self.datum(0);
+                LispVal::dotted_list(items, if matches!(tail, LispVal::Nil) { LispVal::Number(0) } else { tail })
+            }
+        }
+    }
+}
+
+#[test]
+fn write_read_round_trip_test() {
+    use crate::port::Port;
+    for seed in 1..2000u64 {
+        let mut rng = Rng(seed.wrapping_mul(0x9E3779B97F4A7C15));
+        let v = rng.datum(4);
+        let written = print_string(&v, Mode::Write);
+        let read = Port::string_input(&written).read_datum().unwrap();
+        assert!(crate::lispval::equal(&read, &v), "seed {}: {}", seed, written);
+    }
+}
+
+#[test]
+fn printer_test() {
+    use crate::evaluation::eval_str;
+    let env = &crate::primitive_functions::create_eden_env();
+    let run = |s: &str| print_string(&eval_str(s, env).unwrap(), Mode::Write);
+    assert_eq!(run("\"a\\\"b\\\\c\\nd\""), "\"a\\\"b\\\\c\\nd\"");
+    assert_eq!(print_string(&eval_str("'(\"a\" #\\b c)", env).unwrap(), Mode::Display), "(a b c)");
+    assert_eq!(run("''x"), "'x");
+    assert_eq!(run("(string->symbol \"two words\")"), "|two words|");
+    assert_eq!(run("#\\x7"), "#\\alarm");
+    assert_eq!(run("car"), "#<procedure car>");
+    eval_str("(define first car)", env).unwrap();
+    assert_eq!(run("first"), "#<procedure car>");
+    assert_eq!(run("#\\null"), "#\\null");
+    eval_str("(define (f x) x)", env).unwrap();
+    eval_str("(define g (lambda (x) x))", env).unwrap();
+    assert_eq!(run("f"), "#<procedure f>");
+    assert_eq!(run("g"), "#<procedure g>");
+    assert_eq!(run("(lambda (x) x)"), "#<procedure>");
+}
+
+#[test]
+fn datum_label_printer_test() {
+    use crate::evaluation::eval_str;
+    use crate::port::Port;
+    let env = &crate::primitive_functions::create_eden_env();
+    let run = |s: &str| eval_str(s, env).unwrap();
+    run("(define c (list 1 2 3))");
+    run("(set-cdr! (cdr (cdr c)) (cdr c))");
+    assert_eq!(run("c").to_string(), "(1 . #0=(2 3 . #0#))");
+    assert_eq!(print_string(&run("c"), Mode::Display), "(1 . #0=(2 3 . #0#))");
+    run("(define x (list 'a))");
+    run("(define shared (list x x))");
+    assert_eq!(run("shared").to_string(), "((a) (a))");
+    assert_eq!(run("(call-with-output-string (lambda (p) (write-shared shared p)))").to_string(), "\"(#0=(a) #0#)\"");
+    assert_eq!(run("(call-with-output-string (lambda (p) (write-simple shared p)))").to_string(), "\"((a) (a))\"");
+    run("(define d (list 1 2))");
+    run("(set-car! d d)");
+    assert_eq!(run("d").to_string(), "#0=(#0# 2)");
+    run("(define-record-type <box> (box v) box? (v box-v set-box-v!))");
+    run("(define b (box c))");
+    assert_eq!(run("b").to_string(), "#<box v: (1 . #0=(2 3 . #0#))>");
+    assert!(is_circular(&run("b")));
+    run("(define loop (list 1))");
+    run("(set-box-v! b loop)");
+    run("(set-car! loop b)");
+    assert_eq!(run("b").to_string(), "#<box v: (#<box>)>");
+    assert_eq!(run("(values c 1)").to_string(), "(1 . #0=(2 3 . #0#)) 1");
+    for s in ["#0=(a . #0#)", "#0=(#1=(b . #1#) . #0#)", "(1 . #0=(2 3 . #0#))", "#0=(a '#0#)"] {
+        let v = Port::string_input(s).read_datum().unwrap();
+        assert_eq!(v.to_string(), s);
+    }
+}
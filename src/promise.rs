@@ -0,0 +1,129 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::env::Env;
+use crate::error::LispErr;
+use crate::evaluation::eval;
+use crate::lispval::LispVal;
+use crate::lispval::LispVal::Boolean;
+use crate::port::Port;
+use crate::prelude::eval_port;
+use crate::primitive_functions::expect_args;
+
+#[derive(Clone, Debug)]
+pub enum PromiseState {
+    Done(LispVal),
+    /// An expression still to be evaluated. With `lazy` set it comes from `delay-force` and
+    /// evaluates to another promise.
+    Delayed { expr: LispVal, env: Rc<RefCell<Env>>, lazy: bool },
+}
+
+/// A promise as in the R7RS reference implementation: a box around a state that
+/// `delay-force` chains share, so forcing a long chain runs in constant space.
+#[derive(Debug)]
+pub struct Promise {
+    state: RefCell<Rc<RefCell<PromiseState>>>,
+}
+
+impl Promise {
+    pub fn new(state: PromiseState) -> Promise {
+        Promise { state: RefCell::new(Rc::new(RefCell::new(state))) }
+    }
+
+    fn current(&self) -> PromiseState {
+        self.state.borrow().borrow().clone()
+    }
+
+    fn set(&self, state: PromiseState) {
+        *self.state.borrow().borrow_mut() = state;
+    }
+
+    pub fn force(&self) -> Result<LispVal, LispErr> {
+        loop {
+            let PromiseState::Delayed { expr, env, lazy } = self.current() else {
+                break;
+            };
+            let v = eval(&expr, &env)?;
+            // Forcing the promise again from inside `expr` may already have finished it.
+            if let PromiseState::Done(_) = self.current() {
+                break;
+            }
+            match v {
+                LispVal::Promise(next) if lazy => {
+                    self.set(next.current());
+                    *next.state.borrow_mut() = self.state.borrow().clone();
+                }
+                v => self.set(PromiseState::Done(v)),
+            }
+        }
+        match self.current() {
+            PromiseState::Done(v) => Ok(v),
+            PromiseState::Delayed { .. } => unreachable!(),
+        }
+    }
+}
+
+fn force(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 1)?;
+    match &a[0] {
+        LispVal::Promise(p) => p.force(),
+        v => Ok(v.clone()),
+    }
+}
+
+fn make_promise(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 1)?;
+    match &a[0] {
+        p @ LispVal::Promise(_) => Ok(p.clone()),
+        v => Ok(LispVal::Promise(Rc::new(Promise::new(PromiseState::Done(v.clone()))))),
+    }
+}
+
+pub fn define_promise_functions(e: &mut Env) {
+    e.define_primitive("force", force).unwrap();
+    e.define_primitive("make-promise", make_promise).unwrap();
+    e.define_primitive("promise?", |a, _| { expect_args(a, 1)?; Ok(Boolean(matches!(a[0], LispVal::Promise(_)))) }).unwrap();
+}
+
+/// Defines the SRFI-41 stream procedures, which are written in Lisp on top of promises.
+pub fn define_stream_library(env: &Rc<RefCell<Env>>) {
+    eval_port(&Port::string_input(include_str!("streams.scm")), env).expect("streams.scm should evaluate");
+}
+
+#[test]
+fn promise_test() {
+    use crate::evaluation::eval_str;
+    let env = &crate::primitive_functions::create_eden_env();
+    let run = |s: &str| eval_str(s, env).unwrap().to_string();
+    run("(define count 0)");
+    run("(define p (delay ((lambda () (set! count (+ count 1)) count))))");
+    assert_eq!(run("(promise? p)"), "#t");
+    assert_eq!(run("(force p)"), "1");
+    assert_eq!(run("(force p)"), "1");
+    assert_eq!(run("(force (make-promise 5))"), "5");
+    assert_eq!(run("(force 7)"), "7");
+    run("(define (loop n) (delay-force (if (= n 0) (delay 'done) (loop (- n 1)))))");
+    assert_eq!(run("(force (loop 10000))"), "done");
+}
+
+#[test]
+fn stream_test() {
+    use crate::evaluation::eval_str;
+    let env = &crate::primitive_functions::create_eden_env();
+    let run = |s: &str| eval_str(s, env).unwrap().to_string();
+    run("(define (from n) (stream-cons n (from (+ n 1))))");
+    run("(define naturals (from 0))");
+    assert_eq!(run("(stream-car (stream-cdr naturals))"), "1");
+    assert_eq!(run("(stream->list (stream-take 5 (stream-map (lambda (x) (* x x)) naturals)))"), "(0 1 4 9 16)");
+    assert_eq!(run("(stream->list (stream-take 3 (stream-filter (lambda (x) (= 0 (- x (* 1000 (/ x 1000))))) (stream-cdr naturals))))"),
+               "(1000 2000 3000)");
+    assert_eq!(run("(stream->list (list->stream '(1 2 3)))"), "(1 2 3)");
+    assert_eq!(run("(stream-ref naturals 50)"), "50");
+    assert_eq!(run("(stream-null? (stream-take 0 naturals))"), "#t");
+    run("(define evaluated 0)");
+    run("(define s (stream-cons ((lambda () (set! evaluated 1) 'a)) stream-null))");
+    assert_eq!(run("evaluated"), "0");
+    assert_eq!(run("(stream-car s)"), "a");
+    assert_eq!(run("evaluated"), "1");
+    assert!(eval_str("(stream-car stream-null)", env).is_err());
+}
@@ -0,0 +1,167 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::env::Env;
+use crate::error::LispErr;
+use crate::error::LispErr::{Runtime, WrongType};
+use crate::lispval::{LispVal, PrimitiveFn};
+use crate::lispval::LispVal::{Boolean, Func, PrimitiveFunc, Unspecified};
+use crate::symbol::{symbol, Symbol};
+
+/// A type created by `define-record-type`. Two definitions with the same name are
+/// still different types.
+#[derive(Debug, PartialEq)]
+pub struct RecordType {
+    pub name: Symbol,
+    pub fields: Vec<Symbol>,
+}
+
+impl RecordType {
+    /// `<point>` is printed as `point`.
+    pub fn display_name(&self) -> String {
+        let name = self.name.name();
+        name.strip_prefix('<').and_then(|n| n.strip_suffix('>')).unwrap_or(&name).to_string()
+    }
+
+    fn field_index(&self, field: Symbol) -> Result<usize, LispErr> {
+        self.fields.iter().position(|f| *f == field)
+            .ok_or_else(|| Runtime(format!("Record type {} has no field {}", self.name, field)))
+    }
+}
+
+#[derive(Debug)]
+pub struct Record {
+    pub record_type: Rc<RecordType>,
+    pub values: RefCell<Vec<LispVal>>,
+}
+
+fn record_type(v: &LispVal) -> Result<&Rc<RecordType>, LispErr> {
+    match v {
+        LispVal::RecordType(t) => Ok(t),
+        _ => Err(WrongType("record type".to_string(), v.clone())),
+    }
+}
+
+/// The record `v` if it is an instance of `record_type`.
+fn instance<'a>(record_type: &Rc<RecordType>, v: &'a LispVal) -> Result<&'a Rc<Record>, LispErr> {
+    match v {
+        LispVal::Record(r) if Rc::ptr_eq(&r.record_type, record_type) => Ok(r),
+        _ => Err(WrongType(record_type.display_name(), v.clone())),
+    }
+}
+
+fn index(v: &LispVal) -> Result<usize, LispErr> {
+    Ok(v.num()? as usize)
+}
+
+/// `(construct type (index ...) value ...)` stores each value in the field at the
+/// matching index; fields missing from the constructor stay unspecified.
+fn construct(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    let record_type = record_type(&a[0])?;
+    let mut values = vec![Unspecified; record_type.fields.len()];
+    for (i, v) in a[1].to_vec()?.iter().zip(&a[2..]) {
+        values[index(i)?] = v.clone();
+    }
+    Ok(LispVal::Record(Rc::new(Record { record_type: record_type.clone(), values: RefCell::new(values) })))
+}
+
+/// `(is-instance type obj)`
+fn is_instance(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    Ok(Boolean(instance(record_type(&a[0])?, &a[1]).is_ok()))
+}
+
+/// `(get-field type index obj)`
+fn get_field(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    let record = instance(record_type(&a[0])?, &a[2])?;
+    let value = record.values.borrow()[index(&a[1])?].clone();
+    Ok(value)
+}
+
+/// `(set-field type index obj value)`
+fn set_field(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    let record = instance(record_type(&a[0])?, &a[2])?;
+    record.values.borrow_mut()[index(&a[1])?] = a[3].clone();
+    Ok(Unspecified)
+}
+
+/// A procedure of `args` whose body applies `primitive` to the record type and
+/// `constants` followed by the arguments. The record type is embedded as a value, so the
+/// procedure works whatever the environment it was defined in binds.
+fn procedure(name: Symbol, primitive: PrimitiveFn, record_type: &Rc<RecordType>,
+             constants: Vec<LispVal>, args: Vec<Symbol>, env: &Rc<RefCell<Env>>) -> LispVal {
+    let mut call = vec![PrimitiveFunc { name: None, func: primitive }, LispVal::RecordType(record_type.clone())];
+    call.extend(constants.into_iter().map(|c| LispVal::Quote(Box::new(c))));
+    call.extend(args.iter().map(|a| LispVal::Symbol(*a)));
+    Func { name: Some(name), args, optional: Rc::new([]), vararg: None, keys: Rc::new([]),
+         body: Rc::new([LispVal::list(call)]), closure: env.clone() }
+}
+
+/// Binds the type, constructor, predicate and field procedures of
+/// `(define-record-type <name> (constructor field ...) predicate (field accessor [modifier]) ...)`.
+/// The constructor spec may also be a bare name, taking every field, or `#f` for none.
+pub fn define_record(spec: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    let [type_name, constructor, predicate, field_specs @ ..] = spec else {
+        return Err(Runtime("define-record-type: expected type name, constructor and predicate".to_string()));
+    };
+    let field_specs = field_specs.iter().map(|f| f.to_vec()).collect::<Result<Vec<_>, _>>()?;
+    let fields = field_specs.iter()
+        .map(|f| f.first().ok_or_else(|| Runtime("define-record-type: empty field spec".to_string())).and_then(symbol))
+        .collect::<Result<Vec<_>, _>>()?;
+    let record_type = Rc::new(RecordType { name: symbol(type_name)?, fields });
+    let mut e = env.borrow_mut();
+    e.define(record_type.name, LispVal::RecordType(record_type.clone()))?;
+
+    let (constructor, args) = match constructor {
+        Boolean(false) => (None, vec![]),
+        LispVal::Symbol(name) => (Some(*name), record_type.fields.clone()),
+        _ => {
+            let spec = constructor.to_vec()?;
+            let name = symbol(spec.first().ok_or_else(|| Runtime("define-record-type: empty constructor spec".to_string()))?)?;
+            (Some(name), spec[1..].iter().map(symbol).collect::<Result<Vec<_>, _>>()?)
+        }
+    };
+    if let Some(name) = constructor {
+        let indices = args.iter().map(|a| record_type.field_index(*a).map(|i| LispVal::Number(i as i64)))
+            .collect::<Result<Vec<_>, _>>()?;
+        e.define(name, procedure(name, construct, &record_type, vec![LispVal::list(indices)], args, env))?;
+    }
+    let predicate = symbol(predicate)?;
+    e.define(predicate, procedure(predicate, is_instance, &record_type, vec![], vec![Symbol::intern("obj")], env))?;
+
+    for (i, spec) in field_specs.iter().enumerate() {
+        let index = vec![LispVal::Number(i as i64)];
+        if let Some(accessor) = spec.get(1) {
+            let accessor = symbol(accessor)?;
+            e.define(accessor, procedure(accessor, get_field, &record_type, index.clone(), vec![Symbol::intern("obj")], env))?;
+        }
+        if let Some(modifier) = spec.get(2) {
+            let modifier = symbol(modifier)?;
+            let args = vec![Symbol::intern("obj"), Symbol::intern("value")];
+            e.define(modifier, procedure(modifier, set_field, &record_type, index, args, env))?;
+        }
+    }
+    Ok(Unspecified)
+}
+
+#[test]
+fn record_test() {
+    use crate::evaluation::eval_str;
+    let env = &crate::primitive_functions::create_eden_env();
+    let run = |s: &str| eval_str(s, env).unwrap().to_string();
+    run("(define-record-type <point> (make-point y x) point? (x point-x set-point-x!) (y point-y) (label point-label))");
+    run("(define p (make-point 1 2))");
+    assert_eq!(run("(point-x p)"), "2");
+    assert_eq!(run("(point-y p)"), "1");
+    assert_eq!(run("(point? p)"), "#t");
+    assert_eq!(run("(point? 5)"), "#f");
+    run("(set-point-x! p 10)");
+    assert_eq!(run("(point-x p)"), "10");
+    assert_eq!(run("p"), "#<point x: 10 y: 1 label: #<unspecified>>");
+    assert_eq!(run("point-x"), "#<procedure point-x>");
+    run("(define-record-type <other> make-other other? (x other-x))");
+    assert_eq!(run("(other-x (make-other 3))"), "3");
+    assert_eq!(run("(point? (make-other 3))"), "#f");
+    assert!(matches!(eval_str("(point-x (make-other 3))", env), Err(WrongType(..))));
+    assert!(eval_str("(point-x 5)", env).is_err());
+    assert!(eval_str("(define-record-type <bad> (make-bad z) bad? (x bad-x))", env).is_err());
+}
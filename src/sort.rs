@@ -0,0 +1,138 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::env::Env;
+use crate::error::LispErr;
+use crate::error::LispErr::Runtime;
+use crate::evaluation::call_function;
+use crate::list_functions::is_true;
+use crate::lispval::LispVal;
+use crate::primitive_functions::expect_args;
+
+fn less(less: &LispVal, a: &LispVal, b: &LispVal, env: &Rc<RefCell<Env>>) -> Result<bool, LispErr> {
+    Ok(is_true(&call_function(less, &[a.clone(), b.clone()], env)?))
+}
+
+/// Stable merge of two sorted runs: on ties the element of `left` comes first.
+fn merge_sorted(left: Vec<LispVal>, right: Vec<LispVal>, less_proc: &LispVal,
+                env: &Rc<RefCell<Env>>) -> Result<Vec<LispVal>, LispErr> {
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        if less(less_proc, r, l, env)? {
+            merged.extend(right.next());
+        } else {
+            merged.extend(left.next());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+/// Stable merge sort calling back into Lisp for every comparison. The input is only
+/// consumed, so an error raised by the comparator leaves the caller's data untouched.
+pub fn sort_values(items: Vec<LispVal>, less_proc: &LispVal, env: &Rc<RefCell<Env>>) -> Result<Vec<LispVal>, LispErr> {
+    if items.len() < 2 {
+        return Ok(items);
+    }
+    let mut items = items;
+    let right = items.split_off(items.len() / 2);
+    let left = sort_values(items, less_proc, env)?;
+    let right = sort_values(right, less_proc, env)?;
+    merge_sorted(left, right, less_proc, env)
+}
+
+fn sorted_list(list: &LispVal, less_proc: &LispVal, env: &Rc<RefCell<Env>>) -> Result<Vec<LispVal>, LispErr> {
+    sort_values(list.to_vec()?, less_proc, env)
+}
+
+/// `(sort list less?)`
+fn sort(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 2)?;
+    Ok(LispVal::list(sorted_list(&a[0], &a[1], env)?))
+}
+
+/// `(sort! list less?)` sorts by overwriting the cars of the existing pairs once every
+/// comparison has succeeded and the list is checked to still have the same length.
+fn sort_in_place(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 2)?;
+    let sorted = sorted_list(&a[0], &a[1], env)?;
+    let mut pairs = Vec::with_capacity(sorted.len());
+    let mut l = a[0].clone();
+    while pairs.len() < sorted.len() {
+        let LispVal::Pair(p) = l else { break };
+        l = p.cdr();
+        pairs.push(p);
+    }
+    if pairs.len() != sorted.len() || l != LispVal::Nil {
+        return Err(Runtime("List changed while sorting".to_string()));
+    }
+    for (p, v) in pairs.iter().zip(sorted) {
+        *p.car.borrow_mut() = v;
+    }
+    Ok(a[0].clone())
+}
+
+/// `(list-sort less? list)`
+fn list_sort(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 2)?;
+    Ok(LispVal::list(sorted_list(&a[1], &a[0], env)?))
+}
+
+/// `(merge list1 list2 less?)`
+fn merge(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 3)?;
+    Ok(LispVal::list(merge_sorted(a[0].to_vec()?, a[1].to_vec()?, &a[2], env)?))
+}
+
+/// `(list-merge less? list1 list2)`
+fn list_merge(a: &[LispVal], env: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 3)?;
+    Ok(LispVal::list(merge_sorted(a[1].to_vec()?, a[2].to_vec()?, &a[0], env)?))
+}
+
+/// Defines the SRFI-132 style sorting procedures for lists. `vector-sort` and
+/// `vector-sort!` wait on a vector type; `merge_sorted` already sorts any `Vec` of values.
+pub fn define_sort_functions(e: &mut Env) {
+    e.define_primitive("sort", sort).unwrap();
+    e.define_primitive("sort!", sort_in_place).unwrap();
+    e.define_primitive("list-sort", list_sort).unwrap();
+    e.define_primitive("list-stable-sort", list_sort).unwrap();
+    e.define_primitive("merge", merge).unwrap();
+    e.define_primitive("list-merge", list_merge).unwrap();
+}
+
+#[test]
+fn sort_test() {
+    use crate::evaluation::eval_str;
+    let env = &crate::primitive_functions::create_eden_env();
+    let run = |s: &str| eval_str(s, env).unwrap().to_string();
+    assert_eq!(run("(sort '(3 1 2) <)"), "(1 2 3)");
+    assert_eq!(run("(list-sort < '(5 4 3 2 1 0))"), "(0 1 2 3 4 5)");
+    assert_eq!(run("(list-sort (lambda (a b) (< (car a) (car b))) '((1 . a) (0 . b) (1 . c) (0 . d)))"),
+               "((0 . b) (0 . d) (1 . a) (1 . c))");
+    assert_eq!(run("(merge '(1 3 5) '(2 4) <)"), "(1 2 3 4 5)");
+    run("(define l (list 2 3 1))");
+    run("(define tail (cdr l))");
+    run("(sort! l <)");
+    assert_eq!(run("l"), "(1 2 3)");
+    assert_eq!(run("tail"), "(2 3)");
+}
+
+#[test]
+fn sort_error_test() {
+    use crate::evaluation::eval_str;
+    let env = &crate::primitive_functions::create_eden_env();
+    eval_str("(define l (list 2 \"a\" 1))", env).unwrap();
+    assert!(eval_str("(sort! l <)", env).is_err());
+    assert_eq!(eval_str("l", env).unwrap().to_string(), "(2 \"a\" 1)");
+    assert!(eval_str("(sort '(1 . 2) <)", env).is_err());
+    eval_str("(define m (list 3 2 1))", env).unwrap();
+    assert!(eval_str("(sort! m (lambda (a b) (set-cdr! m '()) (< a b)))", env).is_err());
+    assert_eq!(eval_str("m", env).unwrap().to_string(), "(3)");
+    eval_str("(define n (list 3 2 1))", env).unwrap();
+    assert!(eval_str("(sort! n (lambda (a b) (set-cdr! (cdr (cdr n)) (list 0)) (< a b)))", env).is_err());
+    assert_eq!(eval_str("n", env).unwrap().to_string(), "(3 2 1 0)");
+}
@@ -0,0 +1,55 @@
+(define stream-null (delay '()))
+
+(define (stream? obj) (promise? obj))
+
+(define (stream-null? s) (eqv? (force s) '()))
+
+(define (stream-pair? s) (if (promise? s) (pair? (force s)) #f))
+
+(define (stream-car s)
+  (if (stream-pair? s)
+      (force (car (force s)))
+      (error "stream-car: not a stream pair" s)))
+
+(define (stream-cdr s)
+  (if (stream-pair? s)
+      (cdr (force s))
+      (error "stream-cdr: not a stream pair" s)))
+
+(define (stream-map f s)
+  (delay-force
+    (if (stream-null? s)
+        stream-null
+        (stream-cons (f (stream-car s)) (stream-map f (stream-cdr s))))))
+
+(define (stream-filter pred? s)
+  (delay-force
+    (if (stream-null? s)
+        stream-null
+        (if (pred? (stream-car s))
+            (stream-cons (stream-car s) (stream-filter pred? (stream-cdr s)))
+            (stream-filter pred? (stream-cdr s))))))
+
+(define (stream-take n s)
+  (delay-force
+    (if (= n 0)
+        stream-null
+        (if (stream-null? s)
+            stream-null
+            (stream-cons (stream-car s) (stream-take (- n 1) (stream-cdr s)))))))
+
+(define (stream-ref s n)
+  (if (= n 0)
+      (stream-car s)
+      (stream-ref (stream-cdr s) (- n 1))))
+
+(define (list->stream l)
+  (delay-force
+    (if (eqv? l '())
+        stream-null
+        (stream-cons (car l) (list->stream (cdr l))))))
+
+(define (stream->list s)
+  (if (stream-null? s)
+      '()
+      (cons (stream-car s) (stream->list (stream-cdr s)))))
@@ -0,0 +1,150 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::rc::Rc;
+
+use crate::env::Env;
+use crate::error::LispErr;
+use crate::error::LispErr::Runtime;
+use crate::lispval::LispVal;
+use crate::lispval::LispVal::{Boolean, LispString};
+use crate::primitive_functions::expect_args;
+
+/// An interned symbol. Symbols with the same name share an id, so comparing and hashing
+/// them never touches the name. Uninterned symbols take fresh ids from a separate range, and
+/// their names are kept out of the interning table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+/// The first id of an uninterned symbol.
+const UNINTERNED: u32 = 1 << 31;
+
+#[derive(Default)]
+struct SymbolTable {
+    names: Vec<Rc<str>>,
+    ids: HashMap<Rc<str>, u32>,
+    /// Names given to uninterned symbols, by id.
+    uninterned: HashMap<u32, Rc<str>>,
+    next_uninterned: u32,
+}
+
+thread_local! {
+    static SYMBOLS: RefCell<SymbolTable> = RefCell::new(SymbolTable::default());
+}
+
+impl Symbol {
+    pub fn intern(name: &str) -> Symbol {
+        SYMBOLS.with(|t| {
+            let mut t = t.borrow_mut();
+            if let Some(id) = t.ids.get(name) {
+                return Symbol(*id);
+            }
+            let id = t.names.len() as u32;
+            let name: Rc<str> = Rc::from(name);
+            t.names.push(name.clone());
+            t.ids.insert(name, id);
+            Symbol(id)
+        })
+    }
+
+    pub fn uninterned(name: &str) -> Symbol {
+        let symbol = Symbol::unnamed();
+        SYMBOLS.with(|t| t.borrow_mut().uninterned.insert(symbol.0, Rc::from(name)));
+        symbol
+    }
+
+    /// An uninterned symbol with an empty name, which costs no memory to keep around.
+    pub fn unnamed() -> Symbol {
+        SYMBOLS.with(|t| {
+            let mut t = t.borrow_mut();
+            let id = UNINTERNED.checked_add(t.next_uninterned).expect("too many uninterned symbols");
+            t.next_uninterned += 1;
+            Symbol(id)
+        })
+    }
+
+    pub fn name(&self) -> Rc<str> {
+        SYMBOLS.with(|t| {
+            let t = t.borrow();
+            match self.0 < UNINTERNED {
+                true => t.names[self.0 as usize].clone(),
+                false => t.uninterned.get(&self.0).cloned().unwrap_or_else(|| Rc::from("")),
+            }
+        })
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Self {
+        Symbol::intern(name)
+    }
+}
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+pub fn symbol(v: &LispVal) -> Result<Symbol, LispErr> {
+    match v {
+        LispVal::Symbol(s) => Ok(*s),
+        _ => Err(Runtime(format!("Expected symbol but got {}", v))),
+    }
+}
+
+fn is_symbol(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 1)?;
+    Ok(Boolean(matches!(a[0], LispVal::Symbol(_))))
+}
+
+fn symbol_to_string(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 1)?;
+    Ok(LispString(symbol(&a[0])?.name().to_string()))
+}
+
+fn string_to_symbol(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 1)?;
+    Ok(LispVal::Symbol(Symbol::intern(&a[0].str()?)))
+}
+
+fn string_to_uninterned_symbol(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    expect_args(a, 1)?;
+    Ok(LispVal::Symbol(Symbol::uninterned(&a[0].str()?)))
+}
+
+fn symbol_eq(a: &[LispVal], _: &Rc<RefCell<Env>>) -> Result<LispVal, LispErr> {
+    let symbols = a.iter().map(symbol).collect::<Result<Vec<_>, _>>()?;
+    Ok(Boolean(symbols.windows(2).all(|w| w[0] == w[1])))
+}
+
+pub fn define_symbol_functions(e: &mut Env) {
+    e.define_primitive("symbol?", is_symbol).unwrap();
+    e.define_primitive("symbol->string", symbol_to_string).unwrap();
+    e.define_primitive("string->symbol", string_to_symbol).unwrap();
+    e.define_primitive("string->uninterned-symbol", string_to_uninterned_symbol).unwrap();
+    e.define_primitive("symbol=?", symbol_eq).unwrap();
+}
+
+#[test]
+fn symbol_test() {
+    use crate::evaluation::eval_str;
+    let env = &crate::primitive_functions::create_eden_env();
+    let run = |s: &str| eval_str(s, env).unwrap().to_string();
+    assert_eq!(Symbol::intern("foo"), Symbol::intern("foo"));
+    assert_ne!(Symbol::intern("foo"), Symbol::uninterned("foo"));
+    assert_eq!(Symbol::uninterned("foo").name().as_ref(), "foo");
+    assert_ne!(Symbol::unnamed(), Symbol::unnamed());
+    let interned = || SYMBOLS.with(|t| t.borrow().names.len());
+    crate::parser::parse_expr("x").unwrap();
+    let before = interned();
+    for _ in 0..3 {
+        crate::parser::parse_expr("#0=(x . #0#)").unwrap();
+    }
+    assert_eq!(interned(), before);
+    assert_eq!(run("(eq? 'abc (string->symbol \"abc\"))"), "#t");
+    assert_eq!(run("(symbol->string 'abc)"), "\"abc\"");
+    assert_eq!(run("(symbol=? 'a 'a 'a)"), "#t");
+    assert_eq!(run("(symbol? (string->uninterned-symbol \"abc\"))"), "#t");
+    assert_eq!(run("(eq? 'abc (string->uninterned-symbol \"abc\"))"), "#f");
+}
@@ -0,0 +1,94 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use lisp::{Arity, Interpreter, LispErr, LispVal};
+
+#[test]
+fn eval_str_test() {
+    let lisp = Interpreter::new().unwrap();
+    assert_eq!(lisp.eval_str("(define x 20) ; the answer\n(+ x 22)").unwrap(), LispVal::Number(42));
+    assert_eq!(lisp.eval_str("").unwrap(), LispVal::Unspecified);
+    assert_eq!(lisp.eval_str("(cadr '(1 2))").unwrap().to_string(), "2");
+    assert!(matches!(lisp.eval_str("(car '())"), Err(LispErr::Runtime(_))));
+}
+
+#[test]
+fn globals_test() {
+    let lisp = Interpreter::without_prelude();
+    lisp.define_global("greeting", LispVal::LispString("hello".to_string())).unwrap();
+    assert_eq!(lisp.eval_str("greeting").unwrap().to_string(), "\"hello\"");
+    lisp.eval_str("(define n (* 6 7))").unwrap();
+    assert_eq!(lisp.get_global("n").unwrap(), LispVal::Number(42));
+    assert!(lisp.get_global("missing").is_err());
+    assert!(lisp.eval_str("(null? '())").is_err());
+}
+
+#[test]
+fn call_test() {
+    let lisp = Interpreter::new().unwrap();
+    lisp.eval_str("(define (pair-up a b) (list a b))").unwrap();
+    let list = lisp.call("pair-up", &[LispVal::symbol("x"), LispVal::Number(1)]).unwrap();
+    assert_eq!(list.to_string(), "(x 1)");
+    assert_eq!(lisp.call("+", &[LispVal::Number(1), LispVal::Number(2)]).unwrap(), LispVal::Number(3));
+    assert!(lisp.call("pair-up", &[]).is_err());
+    assert!(lisp.call("undefined-procedure", &[]).is_err());
+}
+
+#[test]
+fn eval_file_test() {
+    let path = std::env::temp_dir().join(format!("lisp-interpreter-test-{}.scm", std::process::id()));
+    std::fs::write(&path, "(define (triple x) (* 3 x))\n(triple 5)\n").unwrap();
+    let lisp = Interpreter::new().unwrap();
+    assert_eq!(lisp.eval_file(&path).unwrap(), LispVal::Number(15));
+    assert_eq!(lisp.call("triple", &[LispVal::Number(2)]).unwrap(), LispVal::Number(6));
+    std::fs::remove_file(&path).unwrap();
+    assert!(matches!(lisp.eval_file(&path), Err(LispErr::Io(..))));
+}
+
+#[test]
+fn separate_interpreters_test() {
+    let a = Interpreter::new().unwrap();
+    let b = Interpreter::new().unwrap();
+    a.eval_str("(define only-in-a 1)").unwrap();
+    assert!(b.get_global("only-in-a").is_err());
+}
+
+#[test]
+fn native_procedure_test() {
+    let lisp = Interpreter::new().unwrap();
+    let counter = Rc::new(Cell::new(0));
+    let next = counter.clone();
+    lisp.define_native("next-id", Arity::Exactly(0), move |_| {
+        next.set(next.get() + 1);
+        Ok(LispVal::Number(next.get()))
+    }).unwrap();
+    assert_eq!(lisp.eval_str("(list (next-id) (next-id))").unwrap().to_string(), "(1 2)");
+    assert_eq!(counter.get(), 2);
+    assert!(matches!(lisp.eval_str("(next-id 1)"), Err(LispErr::Runtime(_))));
+    assert_eq!(counter.get(), 2);
+    lisp.define_native("sum", Arity::AtLeast(1), |args| {
+        let mut total = 0;
+        for a in args {
+            match a {
+                LispVal::Number(n) => total += n,
+                _ => return Err(LispErr::WrongType("number".to_string(), a.clone())),
+            }
+        }
+        Ok(LispVal::Number(total))
+    }).unwrap();
+    assert_eq!(lisp.eval_str("(map sum '(1 2) '(10 20))").unwrap().to_string(), "(11 22)");
+    assert!(lisp.eval_str("(sum)").is_err());
+    assert!(lisp.eval_str("(sum 'a)").is_err());
+    assert_eq!(lisp.eval_str("next-id").unwrap().to_string(), "#<procedure next-id>");
+    assert_eq!(lisp.eval_str("(list (eq? sum sum) (eq? sum next-id))").unwrap().to_string(), "(#t #f)");
+    let sum = lisp.get_global("sum").unwrap();
+    assert_eq!(sum, lisp.get_global("sum").unwrap());
+    assert!(format!("{:?}", sum).contains("AtLeast(1)"));
+}
+
+#[test]
+fn repl_closed_port_test() {
+    let lisp = Interpreter::without_prelude();
+    lisp.eval_str("(close-port (current-output-port))").unwrap();
+    assert!(matches!(lisp.repl(), Err(LispErr::Runtime(_))));
+}